@@ -0,0 +1,160 @@
+use std::{
+    error::Error,
+    io::{self, Read},
+    str::FromStr,
+};
+
+use crate::{
+    codegen::{self, Language},
+    data::{
+        connection_package::OpenApiRequest, endpoint_data_type::EndpointDataType,
+        endpoint_dependency::EndpointDependency,
+    },
+    graph_export::{self, ExportFormat, ExportGranularity, ExportOptions},
+    http_client::url_matcher::UrlMatcher,
+    openapi,
+};
+
+static USAGE: &str = r#"Usage: kmamiz_data_processor <command> [options] < input.json
+
+Without a command, the data processor server is started.
+
+Commands:
+  export    Render endpoint dependencies (JSON array from stdin) as a graph
+            --format <dot|mermaid|graphml|cytoscape>   (default: dot)
+            --granularity <endpoint|service>           (default: service)
+            --namespace <namespace>                    (repeatable)
+            --collapse-versions
+            --edge-labels
+  openapi   Generate OpenAPI 3.1 documents from endpoint datatypes
+            (JSON object with datatypes and optional endpoints from stdin)
+            --service <service|uniqueServiceName>     print only this service's document
+  codegen   Generate models of request and response bodies (JSON array of endpoint datatypes from stdin)
+            --language <rust|go|protobuf>              (default: rust)
+            --service <service|uniqueServiceName>      (repeatable)"#;
+
+/// Runs a command given on the command line, input is read from stdin and output written to stdout.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    match args[0].as_str() {
+        "export" => export(&args[1..]),
+        "openapi" => openapi(&args[1..]),
+        "codegen" => codegen(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        command => Err(format!("unknown command: {command}\n{USAGE}").into()),
+    }
+}
+
+fn read_stdin() -> Result<String, Box<dyn Error>> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    Ok(input)
+}
+
+fn option_value(args: &[String], i: usize) -> Result<&str, Box<dyn Error>> {
+    args.get(i + 1)
+        .map(|v| v.as_str())
+        .ok_or_else(|| format!("missing value for {}", args[i]).into())
+}
+
+fn export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut options = ExportOptions {
+        format: ExportFormat::Dot,
+        granularity: ExportGranularity::Service,
+        namespaces: None,
+        collapse_versions: false,
+        edge_labels: false,
+    };
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                options.format = ExportFormat::from_str(option_value(args, i)?)?;
+                i += 1;
+            }
+            "--granularity" => {
+                options.granularity = ExportGranularity::from_str(option_value(args, i)?)?;
+                i += 1;
+            }
+            "--namespace" => {
+                let namespace = option_value(args, i)?.to_owned();
+                options.namespaces.get_or_insert(vec![]).push(namespace);
+                i += 1;
+            }
+            "--collapse-versions" => options.collapse_versions = true,
+            "--edge-labels" => options.edge_labels = true,
+            arg => return Err(format!("unknown option: {arg}").into()),
+        }
+        i += 1;
+    }
+
+    let dependencies: Vec<EndpointDependency> = serde_json::from_str(&read_stdin()?)?;
+    println!("{}", graph_export::export(&dependencies, &options));
+    Ok(())
+}
+
+fn openapi(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut service = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--service" => {
+                service = Some(option_value(args, i)?);
+                i += 1;
+            }
+            arg => return Err(format!("unknown option: {arg}").into()),
+        }
+        i += 1;
+    }
+
+    let request: OpenApiRequest = serde_json::from_str(&read_stdin()?)?;
+    let documents = openapi::generate(&request.datatypes, &request.endpoints, &UrlMatcher::new());
+    let output = match service {
+        Some(service) => {
+            let document = documents
+                .into_iter()
+                .find(|d| d.service == service || d.unique_service_name == service)
+                .ok_or_else(|| format!("no datatypes recorded for service: {service}"))?;
+            serde_json::to_string_pretty(&document.document)?
+        }
+        None => serde_json::to_string_pretty(&documents)?,
+    };
+    println!("{output}");
+    Ok(())
+}
+
+fn codegen(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut language = Language::Rust;
+    let mut services = vec![];
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--language" => {
+                language = Language::from_str(option_value(args, i)?)?;
+                i += 1;
+            }
+            "--service" => {
+                services.push(option_value(args, i)?);
+                i += 1;
+            }
+            arg => return Err(format!("unknown option: {arg}").into()),
+        }
+        i += 1;
+    }
+
+    let mut datatypes: Vec<EndpointDataType> = serde_json::from_str(&read_stdin()?)?;
+    if !services.is_empty() {
+        datatypes.retain(|d| {
+            services
+                .iter()
+                .any(|&s| d.service == s || d.unique_service_name == s)
+        });
+    }
+    print!(
+        "{}",
+        codegen::from_datatypes(&datatypes, language, &UrlMatcher::new())
+    );
+    Ok(())
+}
@@ -0,0 +1,689 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt::Display,
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{
+    data::{body_sampler, endpoint_data_type::EndpointDataType},
+    http_client::url_matcher::UrlMatcher,
+    json_schema,
+    json_utils::{pascal_case, ObjectSchema, Schema, UniqueNames},
+    openapi,
+};
+
+static HEADER: &str = "Code generated by kmamiz_data_processor from observed samples.";
+static FIELD_NUMBERS: &str =
+    "Field numbers follow the sorted field names and shift when fields are added, regenerate both ends together.";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Go,
+    Protobuf,
+}
+
+#[derive(Debug)]
+pub struct LanguageParseError(String);
+impl Error for LanguageParseError {}
+impl Display for LanguageParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error parsing language: {}", self.0)
+    }
+}
+
+impl FromStr for Language {
+    type Err = LanguageParseError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "rust" | "rs" => Ok(Self::Rust),
+            "go" | "golang" => Ok(Self::Go),
+            "protobuf" | "proto" => Ok(Self::Protobuf),
+            _ => Err(LanguageParseError(input.to_owned())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FieldType {
+    String,
+    Number,
+    Boolean,
+    // unions and values never observed
+    Any,
+    Model(String),
+    Array(Box<FieldType>),
+    // only for array items, fields carry their own flag
+    Nullable(Box<FieldType>),
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    // name in the JSON body
+    name: String,
+    ty: FieldType,
+    nullable: bool,
+    optional: bool,
+}
+
+/// Named types of a root body.
+#[derive(Debug)]
+enum Root {
+    Alias(FieldType),
+    // object bodies with the same fields as an earlier model
+    Same(String),
+}
+
+// types the generated code refers to, models named after them get a suffix
+const RESERVED: [&str; 6] = ["String", "Vec", "Option", "Box", "Serialize", "Deserialize"];
+
+/// Object types found in the schemas, identical objects share one model.
+#[derive(Debug)]
+struct Models {
+    // keyed by the fields of a model
+    models: UniqueNames<Vec<Field>>,
+    roots: BTreeMap<String, Root>,
+}
+
+impl Default for Models {
+    fn default() -> Self {
+        let mut models = UniqueNames::default();
+        RESERVED.iter().for_each(|name| {
+            models.reserve(name);
+        });
+        Models {
+            models,
+            roots: BTreeMap::new(),
+        }
+    }
+}
+
+impl Models {
+    fn unique_name(&mut self, name: &str) -> String {
+        self.models.reserve(&type_name(name))
+    }
+
+    fn add_root(&mut self, name: &str, schema: &Schema) {
+        let name = self.unique_name(name);
+        let root = match &schema.object {
+            Some(obj) if schema.is_only_object() => {
+                let fields = self.fields_of(obj);
+                let key = format!("{fields:?}");
+                match self.models.get(&key) {
+                    Some(existing) => Root::Same(existing.clone()),
+                    None => {
+                        self.models.define(&name, key, fields);
+                        return;
+                    }
+                }
+            }
+            _ if schema.is_only_array() => Root::Alias(self.type_of("ArrayItem", schema).0),
+            _ => Root::Alias(self.type_of(&format!("{name}Object"), schema).0),
+        };
+        self.roots.insert(name, root);
+    }
+
+    /// The type and whether null was seen.
+    fn type_of(&mut self, name: &str, schema: &Schema) -> (FieldType, bool) {
+        let mut types = vec![];
+        if schema.string {
+            types.push(FieldType::String);
+        }
+        if schema.number {
+            types.push(FieldType::Number);
+        }
+        if schema.boolean {
+            types.push(FieldType::Boolean);
+        }
+        if let Some(obj) = &schema.object {
+            types.push(FieldType::Model(self.add_model(name, obj)));
+        }
+        if let Some(items) = &schema.array {
+            let item = match self.type_of(name, items) {
+                (FieldType::Any, _) => FieldType::Any,
+                (item, true) => FieldType::Nullable(Box::new(item)),
+                (item, false) => item,
+            };
+            types.push(FieldType::Array(Box::new(item)));
+        }
+        let ty = match types.len() {
+            1 => types.remove(0),
+            _ => FieldType::Any,
+        };
+        (ty, schema.null)
+    }
+
+    fn fields_of(&mut self, obj: &ObjectSchema) -> Vec<Field> {
+        obj.properties
+            .iter()
+            .map(|(name, property)| {
+                let (ty, nullable) = self.type_of(name, &property.schema);
+                Field {
+                    name: name.clone(),
+                    ty,
+                    nullable,
+                    optional: property.is_optional(obj),
+                }
+            })
+            .collect()
+    }
+
+    fn add_model(&mut self, name: &str, obj: &ObjectSchema) -> String {
+        let fields = self.fields_of(obj);
+        let key = format!("{fields:?}");
+        self.models.add(&type_name(name), key, fields)
+    }
+}
+
+fn type_name(name: &str) -> String {
+    match pascal_case(name) {
+        n if n.is_empty() => "Item".to_owned(),
+        n if n.starts_with(|c: char| c.is_ascii_digit()) => format!("T{n}"),
+        n => n,
+    }
+}
+
+/// Models of every request and response body in the datatypes, named after the method, the
+/// path and the status, e.g. `GetUsersParam1Request` and `GetUsersParam1200Response`.
+pub fn from_datatypes(
+    datatypes: &[EndpointDataType],
+    language: Language,
+    url_matcher: &UrlMatcher,
+) -> String {
+    let mut datatypes = datatypes.iter().collect::<Vec<_>>();
+    datatypes.sort_by(|a, b| a.unique_endpoint_name.cmp(&b.unique_endpoint_name));
+
+    let mut roots = vec![];
+    for datatype in datatypes.into_iter() {
+        let (template, _) = openapi::to_template(&openapi::path_of(datatype, url_matcher));
+        let name = format!("{:?}{}", datatype.method, pascal_case(&template));
+
+        let mut schemas = datatype.schemas.iter().collect::<Vec<_>>();
+        schemas.sort_by(|a, b| a.status.cmp(&b.status));
+        let request = schemas.iter().filter_map(|s| {
+            body_schema(s.request_json_schema.as_ref(), s.request_sample.as_deref())
+        });
+        if let Some(schema) = merge_all(request) {
+            roots.push((format!("{name}Request"), schema));
+        }
+        let mut statuses: BTreeMap<&str, Vec<Schema>> = BTreeMap::new();
+        for schema in schemas.iter() {
+            let bodies = statuses.entry(&schema.status).or_default();
+            bodies.extend(body_schema(
+                schema.response_json_schema.as_ref(),
+                schema.response_sample.as_deref(),
+            ));
+        }
+        for (status, bodies) in statuses.into_iter() {
+            if let Some(schema) = merge_all(bodies.into_iter()) {
+                roots.push((format!("{name}{status}Response"), schema));
+            }
+        }
+    }
+    generate(&roots, language)
+}
+
+/// The stored schema of a body, or the one of its sample for datatypes stored without it.
+/// Truncated samples only tell the length of the body.
+fn body_schema(json_schema: Option<&Value>, sample: Option<&str>) -> Option<Schema> {
+    let schema = match json_schema {
+        Some(json_schema) => json_schema::from_json_schema(json_schema),
+        None => serde_json::from_str::<Value>(sample?)
+            .ok()
+            .filter(|s| !body_sampler::is_truncated(s))
+            .map(|s| Schema::infer(&s))?,
+    };
+    // bodies never observed are stored as objects without properties
+    let is_empty = schema.is_only_object()
+        && schema
+            .object
+            .as_ref()
+            .is_some_and(|o| o.properties.is_empty());
+    (!is_empty && !schema.is_only_null()).then_some(schema)
+}
+
+fn merge_all(schemas: impl Iterator<Item = Schema>) -> Option<Schema> {
+    schemas.reduce(|mut merged, schema| {
+        merged.merge(&schema);
+        merged
+    })
+}
+
+/// Source file with a type for every named schema, nested objects become models of their own.
+pub fn generate(roots: &[(String, Schema)], language: Language) -> String {
+    let mut models = Models::default();
+    for (name, schema) in roots.iter() {
+        models.add_root(name, schema);
+    }
+    match language {
+        Language::Rust => to_rust(&models),
+        Language::Go => to_go(&models),
+        Language::Protobuf => to_protobuf(&models),
+    }
+}
+
+fn to_rust(models: &Models) -> String {
+    fn type_of(ty: &FieldType) -> String {
+        match ty {
+            FieldType::String => "String".to_owned(),
+            FieldType::Number => "f64".to_owned(),
+            FieldType::Boolean => "bool".to_owned(),
+            FieldType::Any => "serde_json::Value".to_owned(),
+            FieldType::Model(name) => name.clone(),
+            FieldType::Array(item) => format!("Vec<{}>", type_of(item)),
+            FieldType::Nullable(item) => format!("Option<{}>", type_of(item)),
+        }
+    }
+
+    let mut blocks = vec![format!(
+        "// {HEADER}\nuse serde::{{Deserialize, Serialize}};"
+    )];
+    for (name, fields) in models.models.defs.iter() {
+        let mut idents = HashSet::new();
+        let mut lines = vec![
+            "#[derive(Serialize, Deserialize, Debug, Clone)]".to_owned(),
+            format!("pub struct {name} {{"),
+        ];
+        for field in fields.iter() {
+            let ident = unique_ident(rust_ident(&field.name), &mut idents);
+            let mut attributes = vec![];
+            if ident.trim_start_matches("r#") != field.name {
+                attributes.push(format!("rename = \"{}\"", escape(&field.name)));
+            }
+            let mut ty = type_of(&field.ty);
+            if field.optional || field.nullable {
+                ty = format!("Option<{ty}>");
+            }
+            if field.optional {
+                attributes.push("default, skip_serializing_if = \"Option::is_none\"".to_owned());
+            }
+            if !attributes.is_empty() {
+                lines.push(format!("    #[serde({})]", attributes.join(", ")));
+            }
+            lines.push(format!("    pub {ident}: {ty},"));
+        }
+        lines.push("}".to_owned());
+        blocks.push(lines.join("\n"));
+    }
+    for (name, root) in models.roots.iter() {
+        let ty = match root {
+            Root::Alias(ty) => type_of(ty),
+            Root::Same(model) => model.clone(),
+        };
+        blocks.push(format!("pub type {name} = {ty};"));
+    }
+    blocks.join("\n\n") + "\n"
+}
+
+fn to_go(models: &Models) -> String {
+    fn type_of(ty: &FieldType) -> String {
+        match ty {
+            FieldType::String => "string".to_owned(),
+            FieldType::Number => "float64".to_owned(),
+            FieldType::Boolean => "bool".to_owned(),
+            FieldType::Any => "interface{}".to_owned(),
+            FieldType::Model(name) => name.clone(),
+            FieldType::Array(item) => format!("[]{}", type_of(item)),
+            FieldType::Nullable(item) => format!("*{}", type_of(item)),
+        }
+    }
+
+    let mut blocks = vec![format!("// {HEADER}\n\npackage models")];
+    for (name, fields) in models.models.defs.iter() {
+        let mut idents = HashSet::new();
+        let rows = fields
+            .iter()
+            .map(|field| {
+                let ident = unique_ident(go_ident(&field.name), &mut idents);
+                let mut ty = type_of(&field.ty);
+                // slices and interfaces are nil already
+                let nillable = matches!(field.ty, FieldType::Array(_) | FieldType::Any);
+                if (field.optional || field.nullable) && !nillable {
+                    ty = format!("*{ty}");
+                }
+                let omit = if field.optional { ",omitempty" } else { "" };
+                let tag = format!("`json:\"{}{omit}\"`", escape(&field.name));
+                (ident, ty, tag)
+            })
+            .collect::<Vec<_>>();
+        // aligned the way gofmt does
+        let ident_width = rows.iter().map(|r| r.0.len()).max().unwrap_or_default();
+        let ty_width = rows.iter().map(|r| r.1.len()).max().unwrap_or_default();
+        let mut lines = vec![format!("type {name} struct {{")];
+        lines.extend(
+            rows.into_iter()
+                .map(|(ident, ty, tag)| format!("\t{ident:ident_width$} {ty:ty_width$} {tag}")),
+        );
+        lines.push("}".to_owned());
+        blocks.push(lines.join("\n"));
+    }
+    for (name, root) in models.roots.iter() {
+        let ty = match root {
+            Root::Alias(ty) => type_of(ty),
+            Root::Same(model) => model.clone(),
+        };
+        blocks.push(format!("type {name} = {ty}"));
+    }
+    blocks.join("\n\n") + "\n"
+}
+
+fn to_protobuf(models: &Models) -> String {
+    // None if the type cannot be repeated
+    fn type_of(ty: &FieldType) -> Option<String> {
+        match ty {
+            FieldType::String => Some("string".to_owned()),
+            FieldType::Number => Some("double".to_owned()),
+            FieldType::Boolean => Some("bool".to_owned()),
+            FieldType::Any => Some("google.protobuf.Value".to_owned()),
+            FieldType::Model(name) => Some(name.clone()),
+            FieldType::Array(_) | FieldType::Nullable(_) => None,
+        }
+    }
+    fn field_of(ty: &FieldType, optional: bool) -> String {
+        match ty {
+            FieldType::Array(item) => match type_of(item) {
+                Some(item) => format!("repeated {item}"),
+                // nested and nullable lists
+                None => "google.protobuf.ListValue".to_owned(),
+            },
+            // messages have presence already
+            FieldType::String | FieldType::Number | FieldType::Boolean if optional => {
+                format!("optional {}", type_of(ty).unwrap())
+            }
+            ty => type_of(ty).unwrap_or_else(|| "google.protobuf.Value".to_owned()),
+        }
+    }
+
+    fn message_of(name: &str, fields: &[Field]) -> String {
+        let mut idents = HashSet::new();
+        let mut lines = vec![format!("message {name} {{")];
+        for (i, field) in fields.iter().enumerate() {
+            let ident = unique_ident(snake_case(&field.name), &mut idents);
+            let ty = field_of(&field.ty, field.optional || field.nullable);
+            let json_name = if ident != field.name {
+                format!(" [json_name = \"{}\"]", escape(&field.name))
+            } else {
+                String::new()
+            };
+            lines.push(format!("  {ty} {ident} = {}{json_name};", i + 1));
+        }
+        lines.push("}".to_owned());
+        lines.join("\n")
+    }
+
+    let mut blocks = models
+        .models
+        .defs
+        .iter()
+        .map(|(name, fields)| message_of(name, fields))
+        .collect::<Vec<_>>();
+    for (name, root) in models.roots.iter() {
+        let block = match root {
+            Root::Alias(ty) => format!(
+                "// the body is the value field\nmessage {name} {{\n  {} value = 1;\n}}",
+                field_of(ty, false)
+            ),
+            // messages cannot be aliased
+            Root::Same(model) => message_of(name, &models.models.defs[model]),
+        };
+        blocks.push(block);
+    }
+
+    let source = blocks.join("\n\n");
+    let mut header =
+        format!("// {HEADER}\n// {FIELD_NUMBERS}\n\nsyntax = \"proto3\";\n\npackage models;");
+    if source.contains("google.protobuf.") {
+        header.push_str("\n\nimport \"google/protobuf/struct.proto\";");
+    }
+    format!("{header}\n\n{source}\n")
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn unique_ident(ident: String, idents: &mut HashSet<String>) -> String {
+    let mut unique = ident.clone();
+    let mut suffix = 1;
+    while idents.contains(&unique) {
+        suffix += 1;
+        unique = format!("{ident}_{suffix}");
+    }
+    idents.insert(unique.clone());
+    unique
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut previous = '_';
+    for c in name.chars() {
+        let c = if c.is_ascii_alphanumeric() { c } else { '_' };
+        if c.is_ascii_uppercase() && (previous.is_ascii_lowercase() || previous.is_ascii_digit()) {
+            snake.push('_');
+        }
+        if c != '_' || !snake.ends_with('_') {
+            snake.push(c.to_ascii_lowercase());
+        }
+        previous = c;
+    }
+    let snake = snake.trim_matches('_');
+    match snake {
+        "" => "field".to_owned(),
+        s if s.starts_with(|c: char| c.is_ascii_digit()) => format!("field_{s}"),
+        s => s.to_owned(),
+    }
+}
+
+fn rust_ident(name: &str) -> String {
+    static KEYWORDS: &[&str] = &[
+        "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod",
+        "move", "mut", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type",
+        "unsafe", "use", "where", "while", "yield", "abstract", "become", "do", "final", "macro",
+        "override", "priv", "typeof", "unsized", "virtual",
+    ];
+    let ident = snake_case(name);
+    match ident.as_str() {
+        // cannot be raw identifiers
+        "self" | "super" | "crate" => format!("{ident}_"),
+        i if KEYWORDS.contains(&i) => format!("r#{ident}"),
+        _ => ident,
+    }
+}
+
+fn go_ident(name: &str) -> String {
+    match pascal_case(name) {
+        n if n.is_empty() => "Field".to_owned(),
+        n if n.starts_with(|c: char| c.is_ascii_digit()) => format!("F{n}"),
+        n => n,
+    }
+}
+
+#[test]
+fn test_generate() {
+    use serde_json::json;
+
+    let roots = vec![
+        (
+            "GetUser200Response".to_owned(),
+            Schema::infer_all(
+                [
+                    json!({ "id": 1, "type": "a", "homeAddress": { "city": "x" }, "tags": ["t", null] }),
+                    json!({ "id": 2, "type": "b", "homeAddress": { "city": "y" }, "tags": [], "note": null }),
+                ]
+                .iter(),
+            ),
+        ),
+        (
+            "GetUsers200Response".to_owned(),
+            Schema::infer(&json!([{ "city": "z" }])),
+        ),
+        (
+            "GetCity200Response".to_owned(),
+            Schema::infer(&json!({ "city": "w" })),
+        ),
+    ];
+
+    assert_eq!(
+        generate(&roots, Language::Rust),
+        r#"// Code generated by kmamiz_data_processor from observed samples.
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetUser200Response {
+    #[serde(rename = "homeAddress")]
+    pub home_address: HomeAddress,
+    pub id: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<serde_json::Value>,
+    pub tags: Vec<Option<String>>,
+    pub r#type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HomeAddress {
+    pub city: String,
+}
+
+pub type GetCity200Response = HomeAddress;
+
+pub type GetUsers200Response = Vec<HomeAddress>;
+"#
+    );
+
+    assert_eq!(
+        generate(&roots, Language::Go),
+        "// Code generated by kmamiz_data_processor from observed samples.
+
+package models
+
+type GetUser200Response struct {
+\tHomeAddress HomeAddress `json:\"homeAddress\"`
+\tId          float64     `json:\"id\"`
+\tNote        interface{} `json:\"note,omitempty\"`
+\tTags        []*string   `json:\"tags\"`
+\tType        string      `json:\"type\"`
+}
+
+type HomeAddress struct {
+\tCity string `json:\"city\"`
+}
+
+type GetCity200Response = HomeAddress
+
+type GetUsers200Response = []HomeAddress
+"
+    );
+
+    assert_eq!(
+        generate(&roots, Language::Protobuf),
+        r#"// Code generated by kmamiz_data_processor from observed samples.
+// Field numbers follow the sorted field names and shift when fields are added, regenerate both ends together.
+
+syntax = "proto3";
+
+package models;
+
+import "google/protobuf/struct.proto";
+
+message GetUser200Response {
+  HomeAddress home_address = 1 [json_name = "homeAddress"];
+  double id = 2;
+  google.protobuf.Value note = 3;
+  google.protobuf.ListValue tags = 4;
+  string type = 5;
+}
+
+message HomeAddress {
+  string city = 1;
+}
+
+message GetCity200Response {
+  string city = 1;
+}
+
+// the body is the value field
+message GetUsers200Response {
+  repeated HomeAddress value = 1;
+}
+"#
+    );
+}
+
+#[test]
+fn test_from_datatypes() {
+    use crate::data::{endpoint_data_type::EndpointDataSchema, request_type::RequestType};
+    use serde_json::json;
+
+    let body = |samples: &[Value]| {
+        let schema = json_schema::to_json_schema(&Schema::infer_all(samples.iter()));
+        (Some(samples[0].to_string()), Some(schema))
+    };
+    let schema = |status: &str, response: (Option<String>, Option<Value>)| EndpointDataSchema {
+        time: 0,
+        status: status.to_owned(),
+        request_sample: None,
+        request_schema: None,
+        request_json_schema: None,
+        request_content_type: None,
+        request_params: None,
+        response_sample: response.0,
+        response_schema: None,
+        response_json_schema: response.1,
+        request_statistics: None,
+        response_statistics: None,
+        response_content_type: None,
+    };
+    let datatype = EndpointDataType {
+        _id: None,
+        unique_service_name: "svc\tns\tv1".to_owned(),
+        unique_endpoint_name: "svc\tns\tv1\tGET\thttp://svc/users".to_owned(),
+        service: "svc".to_owned(),
+        namespace: "ns".to_owned(),
+        version: "v1".to_owned(),
+        label_name: None,
+        method: RequestType::Get,
+        schemas: vec![
+            // the sample has every field, the stored schema tells "nickname" is optional
+            schema(
+                "200",
+                body(&[
+                    json!({ "id": 1, "nickname": "a", "string": { "vec": [] } }),
+                    json!({ "id": 2, "string": { "vec": [] } }),
+                ]),
+            ),
+            // a placeholder for a body too large to keep
+            schema(
+                "500",
+                (Some(json!("<truncated, 1048576 bytes>").to_string()), None),
+            ),
+        ],
+    };
+
+    let code = from_datatypes(&[datatype], Language::Rust, &UrlMatcher::new());
+    assert!(code.contains(
+        "pub struct GetUsers200Response {
+    pub id: f64,
+    #[serde(default, skip_serializing_if = \"Option::is_none\")]
+    pub nickname: Option<String>,
+    pub string: String2,
+}"
+    ));
+    // nested models do not shadow the standard types
+    assert!(code.contains("pub struct String2 {\n    pub vec: Vec<serde_json::Value>,\n}"));
+    assert!(!code.contains("500"));
+}
@@ -0,0 +1,458 @@
+use serde_json::{Map, Value};
+
+// elements nested deeper than this are not converted, the same limit serde_json has for JSON
+const MAX_XML_DEPTH: usize = 128;
+
+/// Turns a body into JSON values for the type inference, picking the parser by content type.
+/// NDJSON bodies give one value per line, unparsable bodies none.
+pub fn parse(body: &str, content_type: Option<&str>) -> Vec<Value> {
+    let content_type = content_type.unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    match media_type.trim().to_lowercase().as_str() {
+        "application/x-ndjson"
+        | "application/ndjson"
+        | "application/jsonl"
+        | "application/x-jsonlines"
+        | "application/stream+json" => parse_ndjson(body),
+        "application/x-www-form-urlencoded" => vec![parse_form(body)],
+        "multipart/form-data" => boundary_of(content_type)
+            .and_then(|boundary| parse_multipart(body, &boundary))
+            .into_iter()
+            .collect(),
+        t if t == "application/xml" || t == "text/xml" || t.ends_with("+xml") => {
+            parse_xml(body).into_iter().collect()
+        }
+        // JSON, or nothing to go on
+        _ => match serde_json::from_str::<Value>(body) {
+            Ok(value) => vec![value],
+            Err(_) if body.trim_start().starts_with('<') => parse_xml(body).into_iter().collect(),
+            Err(_) => parse_ndjson(body),
+        },
+    }
+}
+
+/// Every non-empty line has to be JSON.
+fn parse_ndjson(body: &str) -> Vec<Value> {
+    let lines = body
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(serde_json::from_str::<Value>)
+        .collect::<Result<Vec<_>, _>>();
+    lines.unwrap_or_default()
+}
+
+/// Form fields are strings, the ones that look like numbers or booleans are typed as such.
+/// Codes with a leading zero or plus sign (zip codes, phone numbers) stay strings.
+fn scalar_of(value: &str) -> Value {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let is_code = value.starts_with('+')
+        || (digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0."));
+    if is_code {
+        Value::String(value.to_owned())
+    } else if let Ok(number) = value.parse::<i64>() {
+        Value::from(number)
+    } else if let Some(number) = value.parse::<f64>().ok().filter(|n| n.is_finite()) {
+        Value::from(number)
+    } else if value == "true" || value == "false" {
+        Value::Bool(value == "true")
+    } else {
+        Value::String(value.to_owned())
+    }
+}
+
+/// Repeated fields and ones named `field[]` become arrays.
+fn insert_field(fields: &mut Map<String, Value>, name: &str, value: Value) {
+    let (name, is_array) = match name.strip_suffix("[]") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    match fields.get_mut(name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None if is_array => {
+            fields.insert(name.to_owned(), Value::Array(vec![value]));
+        }
+        None => {
+            fields.insert(name.to_owned(), value);
+        }
+    }
+}
+
+fn parse_form(body: &str) -> Value {
+    let mut fields = Map::new();
+    for pair in body.trim().split('&').filter(|p| !p.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        insert_field(
+            &mut fields,
+            &percent_decode(name),
+            scalar_of(&percent_decode(value)),
+        );
+    }
+    Value::Object(fields)
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .map(|hex| u8::from_str_radix(hex, 16))
+                {
+                    Ok(Ok(byte)) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn boundary_of(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_owned())
+    })
+}
+
+/// Fields of a multipart form, files are described by their name, type and size.
+fn parse_multipart(body: &str, boundary: &str) -> Option<Value> {
+    let delimiter = format!("--{boundary}");
+    let mut fields = Map::new();
+    for part in body.split(&delimiter).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        let part = part.strip_prefix("\r\n").or(part.strip_prefix('\n'))?;
+        let (headers, content) = part
+            .split_once("\r\n\r\n")
+            .or_else(|| part.split_once("\n\n"))?;
+        let content = content
+            .strip_suffix("\r\n")
+            .or(content.strip_suffix('\n'))
+            .unwrap_or(content);
+
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for header in headers.lines() {
+            let Some((key, value)) = header.split_once(':') else {
+                continue;
+            };
+            match key.trim().to_lowercase().as_str() {
+                "content-disposition" => {
+                    for param in value.split(';').skip(1) {
+                        match param.trim().split_once('=') {
+                            Some(("name", v)) => name = Some(v.trim_matches('"').to_owned()),
+                            Some(("filename", v)) => {
+                                filename = Some(v.trim_matches('"').to_owned())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                "content-type" => content_type = Some(value.trim().to_owned()),
+                _ => {}
+            }
+        }
+
+        let value = match filename {
+            Some(filename) => {
+                let mut file = Map::new();
+                file.insert("filename".to_owned(), Value::String(filename));
+                if let Some(content_type) = content_type {
+                    file.insert("contentType".to_owned(), Value::String(content_type));
+                }
+                file.insert("size".to_owned(), Value::from(content.len()));
+                Value::Object(file)
+            }
+            None => match content_type.as_deref() {
+                Some(t) if t.contains("json") || t.contains("xml") => {
+                    parse(content, Some(t)).into_iter().next()?
+                }
+                _ => scalar_of(content),
+            },
+        };
+        insert_field(&mut fields, &name?, value);
+    }
+    Some(Value::Object(fields))
+}
+
+struct Element {
+    name: String,
+    attributes: Map<String, Value>,
+    children: Map<String, Value>,
+    text: String,
+}
+
+impl Element {
+    /// Attributes are prefixed with `@`, text next to child elements is kept as `#text`
+    /// and elements with nothing in them are null.
+    fn into_value(self) -> Value {
+        let text = self.text.trim();
+        if self.attributes.is_empty() && self.children.is_empty() {
+            return if text.is_empty() {
+                Value::Null
+            } else {
+                scalar_of(text)
+            };
+        }
+        let mut object = self.attributes;
+        for (name, value) in self.children.into_iter() {
+            object.insert(name, value);
+        }
+        if !text.is_empty() {
+            object.insert("#text".to_owned(), scalar_of(text));
+        }
+        Value::Object(object)
+    }
+}
+
+/// The document as `{ root: { child: ... } }`, None if it is not well formed.
+/// Documents nested deeper than `MAX_XML_DEPTH` are typed as a plain string.
+fn parse_xml(body: &str) -> Option<Value> {
+    let mut stack: Vec<Element> = vec![];
+    let mut root = None;
+    let mut rest = body.trim();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->")? + 3..];
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>")?;
+            stack.last_mut()?.text.push_str(&after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>')?;
+            let element = stack.pop()?;
+            if after[..end].trim() != element.name {
+                return None;
+            }
+            close(&mut stack, &mut root, element);
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('<') {
+            let end = tag_end(after)?;
+            let (tag, self_closing) = match after[..end].strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (&after[..end], false),
+            };
+            let element = open(tag)?;
+            if self_closing {
+                close(&mut stack, &mut root, element);
+            } else if stack.len() >= MAX_XML_DEPTH {
+                return Some(Value::String(body.to_owned()));
+            } else {
+                stack.push(element);
+            }
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_entities(&rest[..end]);
+            match stack.last_mut() {
+                Some(element) => element.text.push_str(&text),
+                // only whitespace is allowed outside of the root element
+                None if text.trim().is_empty() => {}
+                None => return None,
+            }
+            rest = &rest[end..];
+        }
+        if root.is_some() && !stack.is_empty() {
+            return None;
+        }
+    }
+    if !stack.is_empty() {
+        return None;
+    }
+    root
+}
+
+/// End of a tag, `>` inside quoted attribute values does not count.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn open(tag: &str) -> Option<Element> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = &tag[..name_end];
+    if name.is_empty() {
+        return None;
+    }
+    let mut attributes = Map::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = after[1..].find(quote)? + 1;
+        let value = decode_entities(&after[1..end]);
+        attributes.insert(format!("@{key}"), scalar_of(&value));
+        rest = after[end + 1..].trim_start();
+    }
+    Some(Element {
+        name: name.to_owned(),
+        attributes,
+        children: Map::new(),
+        text: String::new(),
+    })
+}
+
+fn close(stack: &mut [Element], root: &mut Option<Value>, element: Element) {
+    let name = element.name.clone();
+    let value = element.into_value();
+    match stack.last_mut() {
+        Some(parent) => insert_field(&mut parent.children, &name, value),
+        None => {
+            let mut document = Map::new();
+            document.insert(name, value);
+            *root = Some(Value::Object(document));
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let entity = after.find(';').map(|end| (&after[..end], end));
+        let c = entity.and_then(|(entity, _)| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            e => e
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| e.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        });
+        match (c, entity) {
+            (Some(c), Some((_, end))) => {
+                decoded.push(c);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = after;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[test]
+fn test_parse() {
+    use serde_json::json;
+
+    let xml = r#"<?xml version="1.0"?>
+<!-- order -->
+<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope">
+  <soap:Body>
+    <order id="7" express='true'>
+      <item sku="a">2</item>
+      <item sku="b">1</item>
+      <note><![CDATA[<fragile>]]></note>
+      <gift/>
+      <total currency="USD">12.5</total>
+      <owner>Tom &amp; Jerry</owner>
+    </order>
+  </soap:Body>
+</soap:Envelope>"#;
+    let order = json!({
+        "@id": 7,
+        "@express": true,
+        "item": [{ "@sku": "a", "#text": 2 }, { "@sku": "b", "#text": 1 }],
+        "note": "<fragile>",
+        "gift": null,
+        "total": { "@currency": "USD", "#text": 12.5 },
+        "owner": "Tom & Jerry"
+    });
+    assert_eq!(
+        parse(xml, Some("application/soap+xml; charset=utf-8")),
+        vec![json!({
+            "soap:Envelope": {
+                "@xmlns:soap": "http://www.w3.org/2003/05/soap-envelope",
+                "soap:Body": { "order": order }
+            }
+        })]
+    );
+    assert!(parse("<a><b></a>", Some("text/xml")).is_empty());
+    let deep = "<a>".repeat(MAX_XML_DEPTH + 1) + &"</a>".repeat(MAX_XML_DEPTH + 1);
+    assert_eq!(parse(&deep, Some("text/xml")), vec![Value::String(deep)]);
+    let nested = "<a>".repeat(MAX_XML_DEPTH) + &"</a>".repeat(MAX_XML_DEPTH);
+    assert!(parse(&nested, Some("text/xml"))[0].is_object());
+
+    assert_eq!(
+        parse(
+            "name=Tom+Lee&age=30&tags[]=a&tags[]=b%26c&ok=true",
+            Some("application/x-www-form-urlencoded")
+        ),
+        vec![json!({ "name": "Tom Lee", "age": 30, "tags": ["a", "b&c"], "ok": true })]
+    );
+    assert_eq!(
+        parse(
+            "zip=007&phone=%2B886912345678&zero=0&ratio=0.5&delta=-0.25",
+            Some("application/x-www-form-urlencoded")
+        ),
+        vec![json!({
+            "zip": "007",
+            "phone": "+886912345678",
+            "zero": 0,
+            "ratio": 0.5,
+            "delta": -0.25
+        })]
+    );
+
+    let multipart = "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n\
+        --XyZ\r\nContent-Disposition: form-data; name=\"meta\"\r\nContent-Type: application/json\r\n\r\n{\"a\":1}\r\n\
+        --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\nPNG\r\n\
+        --XyZ--\r\n";
+    assert_eq!(
+        parse(multipart, Some("multipart/form-data; boundary=\"XyZ\"")),
+        vec![json!({
+            "title": "hello",
+            "meta": { "a": 1 },
+            "file": { "filename": "a.png", "contentType": "image/png", "size": 3 }
+        })]
+    );
+
+    let ndjson = "{\"id\":1}\n\n{\"id\":2,\"tag\":\"x\"}\n";
+    assert_eq!(
+        parse(ndjson, Some("application/x-ndjson")),
+        vec![json!({ "id": 1 }), json!({ "id": 2, "tag": "x" })]
+    );
+    // bodies without a content type are sniffed
+    assert_eq!(parse(ndjson, None).len(), 2);
+    assert_eq!(parse("<a>1</a>", None), vec![json!({ "a": 1 })]);
+    assert!(parse("plain text", None).is_empty());
+}
@@ -0,0 +1,209 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use serde_json::Value;
+
+use crate::json_utils::{self, Schema};
+
+use super::{body_parser, body_statistics::BodyStatistics};
+
+// distinct samples kept per endpoint and status
+const RESERVOIR_SIZE: usize = 10;
+// elements kept in every array of the merged sample
+const MAX_ARRAY_ITEMS: usize = 10;
+// serialized length of the merged sample, in bytes
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+// characters kept of every string when the sample is too large
+const MAX_STRING_LENGTH: usize = 1024;
+
+/// Streams bodies through schema inference and field statistics, keeping only a bounded
+/// reservoir of distinct samples for the merged sample.
+#[derive(Debug, Default)]
+pub struct BodySampler {
+    // picks the parser, JSON if unknown
+    content_type: Option<String>,
+    schema: Schema,
+    statistics: BodyStatistics,
+    // bodies that were not a duplicate of a kept one
+    distinct: u64,
+    // with their content hashes
+    reservoir: Vec<(u64, Value)>,
+}
+
+impl BodySampler {
+    pub fn new(content_type: Option<String>) -> Self {
+        BodySampler {
+            content_type,
+            ..Default::default()
+        }
+    }
+
+    /// NDJSON bodies are added line by line, bodies that fail to parse are skipped.
+    pub fn add(&mut self, body: &str) {
+        for value in body_parser::parse(body, self.content_type.as_deref()) {
+            self.add_value(value);
+        }
+    }
+
+    fn add_value(&mut self, value: Value) {
+        self.schema.merge(&Schema::infer(&value));
+        self.statistics.add(&value);
+
+        // object keys are sorted, equal bodies serialize the same
+        let hash = hash_of(&value.to_string());
+        // only duplicates of the kept bodies are known, to keep the memory bounded
+        if self.reservoir.iter().any(|(h, _)| *h == hash) {
+            return;
+        }
+        self.distinct += 1;
+        if self.reservoir.len() < RESERVOIR_SIZE {
+            self.reservoir.push((hash, value));
+            return;
+        }
+        // reservoir sampling over the distinct bodies, seeded by the content to stay reproducible
+        let slot = hash_of(&(hash, self.distinct)) % self.distinct;
+        if let Some(kept) = self.reservoir.get_mut(slot as usize) {
+            *kept = (hash, value);
+        }
+    }
+
+    /// The merged sample capped in size, the schema and the field statistics of every body.
+    /// Falls back to the smallest sample with its strings cut, then to a placeholder, see `is_truncated`.
+    pub fn finish(mut self) -> (Value, Schema, BodyStatistics) {
+        self.statistics.update_presence();
+        let reservoir = self
+            .reservoir
+            .into_iter()
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>();
+        let smallest = reservoir
+            .iter()
+            .min_by_key(|v| v.to_string().len())
+            .cloned();
+        let mut merged = json_utils::merge(reservoir);
+        let schema = if self.schema.is_empty() {
+            // no samples, same as typing the empty merged object
+            Schema::infer(&merged)
+        } else {
+            self.schema
+        };
+
+        truncate_arrays(&mut merged);
+        let length = merged.to_string().len();
+        if length <= MAX_BODY_LENGTH {
+            return (merged, schema, self.statistics);
+        }
+        let body = smallest
+            .map(|mut sample| {
+                truncate_arrays(&mut sample);
+                truncate_strings(&mut sample);
+                sample
+            })
+            .filter(|sample| sample.to_string().len() <= MAX_BODY_LENGTH)
+            .unwrap_or_else(|| Value::String(format!("{TRUNCATED_PREFIX}{length} bytes>")));
+        (body, schema, self.statistics)
+    }
+}
+
+const TRUNCATED_PREFIX: &str = "<truncated, ";
+
+/// Whether the sample is the placeholder of a body too large to keep, nothing can be told
+/// from it about the body.
+pub fn is_truncated(sample: &Value) -> bool {
+    sample
+        .as_str()
+        .is_some_and(|s| s.starts_with(TRUNCATED_PREFIX) && s.ends_with(" bytes>"))
+}
+
+fn hash_of(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn truncate_arrays(value: &mut Value) {
+    match value {
+        Value::Array(arr) => {
+            arr.truncate(MAX_ARRAY_ITEMS);
+            arr.iter_mut().for_each(truncate_arrays);
+        }
+        Value::Object(obj) => obj.values_mut().for_each(truncate_arrays),
+        _ => {}
+    }
+}
+
+fn truncate_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => {
+            if let Some((end, _)) = s.char_indices().nth(MAX_STRING_LENGTH) {
+                s.truncate(end);
+                s.push('…');
+            }
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(truncate_strings),
+        Value::Object(obj) => obj.values_mut().for_each(truncate_strings),
+        _ => {}
+    }
+}
+
+#[test]
+fn test_body_sampler() {
+    use serde_json::json;
+
+    let mut sampler = BodySampler::default();
+    for i in 0..1000 {
+        let body = json!([{ "id": i % 50, "tags": vec!["t"; 20] }]);
+        sampler.add(&body.to_string());
+    }
+    sampler.add("not json");
+    assert_eq!(sampler.reservoir.len(), RESERVOIR_SIZE);
+    let mut kept = sampler
+        .reservoir
+        .iter()
+        .map(|(h, _)| *h)
+        .collect::<Vec<_>>();
+    kept.dedup();
+    assert_eq!(kept.len(), RESERVOIR_SIZE);
+    // the first 50 bodies are distinct, later ones only when they are not kept
+    assert!(sampler.distinct >= 50 && sampler.distinct < 1000);
+
+    let (body, schema, statistics) = sampler.finish();
+    // only the reservoir is merged, arrays are cut down
+    assert_eq!(body.as_array().unwrap().len(), MAX_ARRAY_ITEMS);
+    assert_eq!(body[0]["tags"].as_array().unwrap().len(), MAX_ARRAY_ITEMS);
+    // while inference and statistics see every body
+    assert!(schema.is_only_array());
+    assert_eq!(statistics.samples, 1000);
+    assert_eq!(statistics.fields["[].id"].occurrences, 1000);
+
+    let mut sampler = BodySampler::default();
+    sampler.add(&json!({ "text": "a".repeat(MAX_BODY_LENGTH) }).to_string());
+    let (body, schema, _) = sampler.finish();
+    let text = body["text"].as_str().unwrap();
+    assert_eq!(text.chars().count(), MAX_STRING_LENGTH + 1);
+    assert!(text.ends_with('…'));
+    assert!(schema.is_only_object());
+
+    // too many fields to cut down
+    let mut sampler = BodySampler::default();
+    let fields = (0..MAX_BODY_LENGTH)
+        .map(|i| (i.to_string(), json!(i)))
+        .collect::<serde_json::Map<_, _>>();
+    let length = Value::Object(fields.clone()).to_string().len();
+    sampler.add(&Value::Object(fields).to_string());
+    let (body, _, _) = sampler.finish();
+    assert_eq!(body, json!(format!("<truncated, {length} bytes>")));
+    assert!(is_truncated(&body));
+    assert!(!is_truncated(&json!("<truncated>")));
+
+    let (body, schema, statistics) = BodySampler::default().finish();
+    assert_eq!(body, json!({}));
+    assert!(schema.is_only_object());
+    assert_eq!(statistics.samples, 0);
+
+    let mut sampler = BodySampler::new(Some("application/x-www-form-urlencoded".to_owned()));
+    sampler.add("id=1&name=a");
+    sampler.add("id=2");
+    let (body, _, statistics) = sampler.finish();
+    assert_eq!(body, json!({ "id": 2, "name": "a" }));
+    assert_eq!(statistics.fields["name"].presence, 0.5);
+}
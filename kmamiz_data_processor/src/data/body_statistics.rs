@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How often each field of the bodies is present and which value types it had.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyStatistics {
+    // number of bodies observed
+    pub samples: u64,
+    // objects seen at each path, "" is the body itself and "items[]" the objects in an array
+    pub objects: BTreeMap<String, u64>,
+    // keyed by path, e.g. "user.address.city" or "items[].id"
+    pub fields: BTreeMap<String, FieldStatistics>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldStatistics {
+    // path of the objects holding the field
+    pub parent: String,
+    pub occurrences: u64,
+    // occurrences over the objects seen at the parent path
+    pub presence: f64,
+    // string, number, boolean, null, object or array to the number of values
+    pub types: BTreeMap<String, u64>,
+}
+
+impl BodyStatistics {
+    /// Counts the sample, `update_presence` has to be called once all samples are added.
+    pub fn add(&mut self, sample: &Value) {
+        self.samples += 1;
+        self.observe("", sample);
+    }
+
+    fn observe(&mut self, path: &str, value: &Value) {
+        match value {
+            Value::Object(obj) => {
+                *self.objects.entry(path.to_owned()).or_default() += 1;
+                for (name, value) in obj.iter() {
+                    let field_path = if path.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{path}.{name}")
+                    };
+                    let field = self.fields.entry(field_path.clone()).or_default();
+                    field.parent = path.to_owned();
+                    field.occurrences += 1;
+                    *field.types.entry(type_of(value).to_owned()).or_default() += 1;
+                    self.observe(&field_path, value);
+                }
+            }
+            Value::Array(arr) => {
+                let items = format!("{path}[]");
+                arr.iter().for_each(|item| self.observe(&items, item));
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds up the statistics of another window.
+    pub fn merge(&mut self, other: &BodyStatistics) {
+        self.samples += other.samples;
+        for (path, count) in other.objects.iter() {
+            *self.objects.entry(path.clone()).or_default() += count;
+        }
+        for (path, other) in other.fields.iter() {
+            let field = self.fields.entry(path.clone()).or_default();
+            field.parent = other.parent.clone();
+            field.occurrences += other.occurrences;
+            for (ty, count) in other.types.iter() {
+                *field.types.entry(ty.clone()).or_default() += count;
+            }
+        }
+        self.update_presence();
+    }
+
+    pub fn update_presence(&mut self) {
+        for field in self.fields.values_mut() {
+            let parents = self.objects.get(&field.parent).copied().unwrap_or_default();
+            field.presence = if parents > 0 {
+                field.occurrences as f64 / parents as f64
+            } else {
+                0.0
+            };
+        }
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[test]
+fn test_body_statistics() {
+    use serde_json::json;
+
+    let samples = (0..100)
+        .map(|i| match i {
+            0..=2 => {
+                json!({ "id": i, "discount": 0.1, "items": [{ "sku": "a" }, { "sku": null }] })
+            }
+            _ => json!({ "id": i.to_string(), "items": [] }),
+        })
+        .collect::<Vec<_>>();
+    let from_samples = |samples: &[Value]| {
+        let mut statistics = BodyStatistics::default();
+        samples.iter().for_each(|s| statistics.add(s));
+        statistics.update_presence();
+        statistics
+    };
+    let statistics = from_samples(&samples);
+    assert_eq!(statistics.samples, 100);
+    let discount = &statistics.fields["discount"];
+    assert_eq!(discount.presence, 0.03);
+    assert_eq!(discount.types, BTreeMap::from([("number".to_owned(), 3)]));
+    let id = &statistics.fields["id"];
+    assert_eq!(id.presence, 1.0);
+    assert_eq!(
+        id.types,
+        BTreeMap::from([("number".to_owned(), 3), ("string".to_owned(), 97)])
+    );
+    let sku = &statistics.fields["items[].sku"];
+    assert_eq!(sku.parent, "items[]");
+    assert_eq!(sku.presence, 1.0);
+    assert_eq!(sku.types["null"], 3);
+
+    // merging windows is the same as observing them at once
+    let mut merged = from_samples(&samples[..50]);
+    merged.merge(&from_samples(&samples[50..]));
+    assert_eq!(merged, statistics);
+    let mut merged = from_samples(&samples[..1]);
+    merged.merge(&from_samples(&[json!({ "id": 1 })]));
+    assert_eq!(merged.fields["discount"].presence, 0.5);
+}
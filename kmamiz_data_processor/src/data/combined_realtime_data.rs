@@ -1,8 +1,10 @@
 use super::{
+    body_statistics::BodyStatistics,
     endpoint_data_type::{EndpointDataSchema, EndpointDataType},
     request_type::RequestType,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -24,14 +26,26 @@ pub struct CombinedRealtimeData {
     pub namespace: String,
     pub version: String,
     pub latency: CombinedLatency,
+    // sampled requests
     pub combined: usize,
+    // requests extrapolated from the sampling rates
+    pub estimated_combined: f64,
+    // effective sampling rate in percent, combined / estimatedCombined
+    pub sampling_rate: f64,
+    // per second over the processed window, sampled and estimated
+    pub request_rate: f64,
+    pub estimated_request_rate: f64,
     pub status: String,
     pub request_body: Option<String>,
     pub request_schema: Option<String>,
+    pub request_json_schema: Option<Value>,
     pub request_content_type: Option<String>,
+    pub request_statistics: Option<BodyStatistics>,
     pub response_body: Option<String>,
     pub response_schema: Option<String>,
+    pub response_json_schema: Option<Value>,
     pub response_content_type: Option<String>,
+    pub response_statistics: Option<BodyStatistics>,
     pub avg_replica: f64,
 }
 
@@ -54,6 +68,10 @@ impl CombinedRealtimeData {
                     response_content_type: d.response_content_type.clone(),
                     request_schema: d.request_schema.clone(),
                     response_schema: d.response_schema.clone(),
+                    request_json_schema: d.request_json_schema.clone(),
+                    response_json_schema: d.response_json_schema.clone(),
+                    request_statistics: d.request_statistics.clone(),
+                    response_statistics: d.response_statistics.clone(),
                     request_params: None,
                 }],
                 _id: None,
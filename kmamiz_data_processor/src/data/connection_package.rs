@@ -1,7 +1,12 @@
 use super::{
-    combined_realtime_data::CombinedRealtimeData, endpoint_data_type::EndpointDataType,
-    endpoint_dependency::EndpointDependency,
+    combined_realtime_data::CombinedRealtimeData, coupling::CouplingMetrics,
+    critical_path::EndpointCriticalPath, datastore_dependency::DatastoreDependency,
+    dependency_cycle::DependencyCycles, endpoint_data_type::EndpointDataType,
+    endpoint_dependency::EndpointDependency, endpoint_info::EndpointInfo,
+    messaging_dependency::MessagingDependency, risk::RiskResult, sampling::SamplingConfig,
+    schema_change::EndpointSchemaChange, service_dependency::ServiceDependency,
 };
+use crate::{codegen::Language, graph_export::ExportOptions};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,6 +16,13 @@ pub struct RequestPackage {
     pub look_back: u64,
     pub time: u64,
     pub existing_dep: Option<Vec<EndpointDependency>>,
+    // stored datatypes, the new schemas are compared with them
+    pub existing_datatype: Option<Vec<EndpointDataType>>,
+    // in milliseconds, dependencies not observed within it are dropped
+    pub dependency_retention: Option<u64>,
+    // in milliseconds, traces with spans ending within it before `time` are deferred to the next round
+    pub settling_delay: Option<u64>,
+    pub sampling: Option<SamplingConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,6 +31,38 @@ pub struct ResponsePackage {
     pub unique_id: String,
     pub combined: Vec<CombinedRealtimeData>,
     pub dependencies: Vec<EndpointDependency>,
+    pub service_dependencies: Vec<ServiceDependency>,
+    pub messaging_dependencies: Vec<MessagingDependency>,
+    pub datastore_dependencies: Vec<DatastoreDependency>,
+    pub cycles: DependencyCycles,
+    pub coupling: CouplingMetrics,
+    pub risks: Vec<RiskResult>,
     pub datatype: Vec<EndpointDataType>,
+    pub schema_changes: Vec<EndpointSchemaChange>,
+    pub critical_paths: Vec<EndpointCriticalPath>,
     pub log: String,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    pub dependencies: Vec<EndpointDependency>,
+    #[serde(flatten)]
+    pub options: ExportOptions,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenApiRequest {
+    pub datatypes: Vec<EndpointDataType>,
+    // recorded endpoints, their URLs are listed as examples
+    #[serde(default)]
+    pub endpoints: Vec<EndpointInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CodegenRequest {
+    pub datatypes: Vec<EndpointDataType>,
+    pub language: Language,
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    endpoint_dependency::{EndpointDependency, EndpointDependencyItem},
+    endpoint_info::EndpointInfo,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CouplingMetrics {
+    pub services: Vec<ServiceCoupling>,
+    pub endpoints: Vec<EndpointCoupling>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceCoupling {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    #[serde(flatten)]
+    pub coupling: Coupling,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointCoupling {
+    pub unique_service_name: String,
+    pub unique_endpoint_name: String,
+    #[serde(flatten)]
+    pub coupling: Coupling,
+}
+
+/// Only direct (distance 1) dependencies between different services are considered,
+/// calls within a service do not add to its coupling.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Coupling {
+    // distinct callers and callees, services for service coupling and endpoints for endpoint coupling
+    pub fan_in: usize,
+    pub fan_out: usize,
+    // absolute importance, distinct calling services, +1 for gateways
+    pub ais: usize,
+    // absolute dependence, distinct called services
+    pub ads: usize,
+    // absolute criticality, ais * ads
+    pub acs: usize,
+    // fan_out / (fan_in + fan_out), 0 for isolated nodes
+    pub instability: f64,
+}
+
+#[derive(Debug, Default)]
+struct CouplingAccumulator<'a> {
+    callers: BTreeSet<&'a str>,
+    callees: BTreeSet<&'a str>,
+    caller_services: BTreeSet<&'a str>,
+    callee_services: BTreeSet<&'a str>,
+    is_gateway: bool,
+}
+
+impl CouplingMetrics {
+    pub fn from_endpoint_dependencies(dependencies: &[EndpointDependency]) -> CouplingMetrics {
+        let mut services: BTreeMap<&str, (&EndpointInfo, CouplingAccumulator)> = BTreeMap::new();
+        let mut endpoints: BTreeMap<&str, (&EndpointInfo, CouplingAccumulator)> = BTreeMap::new();
+
+        for dep in dependencies.iter() {
+            let info = &dep.endpoint;
+            let (_, service) = services
+                .entry(&info.unique_service_name)
+                .or_insert_with(|| (info, CouplingAccumulator::default()));
+            let (_, endpoint) = endpoints
+                .entry(&info.unique_endpoint_name)
+                .or_insert_with(|| (info, CouplingAccumulator::default()));
+
+            // same as the RiskAnalyzer, an endpoint nobody depends on is an entry point
+            if dep.depending_by.is_empty() {
+                service.is_gateway = true;
+                endpoint.is_gateway = true;
+            }
+            let external = |d: &&EndpointDependencyItem| {
+                d.distance == 1 && d.endpoint.unique_service_name != info.unique_service_name
+            };
+            for item in dep.depending_by.iter().filter(external) {
+                service.callers.insert(&item.endpoint.unique_service_name);
+                service
+                    .caller_services
+                    .insert(&item.endpoint.unique_service_name);
+                endpoint.callers.insert(&item.endpoint.unique_endpoint_name);
+                endpoint
+                    .caller_services
+                    .insert(&item.endpoint.unique_service_name);
+            }
+            for item in dep.depending_on.iter().filter(external) {
+                service.callees.insert(&item.endpoint.unique_service_name);
+                service
+                    .callee_services
+                    .insert(&item.endpoint.unique_service_name);
+                endpoint.callees.insert(&item.endpoint.unique_endpoint_name);
+                endpoint
+                    .callee_services
+                    .insert(&item.endpoint.unique_service_name);
+            }
+        }
+
+        CouplingMetrics {
+            services: services
+                .into_values()
+                .map(|(info, acc)| ServiceCoupling {
+                    unique_service_name: info.unique_service_name.clone(),
+                    service: info.service.clone(),
+                    namespace: info.namespace.clone(),
+                    version: info.version.clone(),
+                    coupling: acc.into(),
+                })
+                .collect(),
+            endpoints: endpoints
+                .into_values()
+                .map(|(info, acc)| EndpointCoupling {
+                    unique_service_name: info.unique_service_name.clone(),
+                    unique_endpoint_name: info.unique_endpoint_name.clone(),
+                    coupling: acc.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<CouplingAccumulator<'_>> for Coupling {
+    fn from(acc: CouplingAccumulator) -> Self {
+        let fan_in = acc.callers.len();
+        let fan_out = acc.callees.len();
+        let ais = acc.caller_services.len() + acc.is_gateway as usize;
+        let ads = acc.callee_services.len();
+        let instability = if fan_in + fan_out == 0 {
+            0.0
+        } else {
+            fan_out as f64 / (fan_in + fan_out) as f64
+        };
+        Coupling {
+            fan_in,
+            fan_out,
+            ais,
+            ads,
+            acs: ais * ads,
+            instability,
+        }
+    }
+}
+
+#[test]
+fn test_coupling() {
+    use crate::data::fixtures::{dependencies, endpoint, URL_A, URL_A2, URL_B, URL_B2, URL_C};
+
+    let (a, a2, b, b2, c) = (
+        endpoint(URL_A),
+        endpoint(URL_A2),
+        endpoint(URL_B),
+        endpoint(URL_B2),
+        endpoint(URL_C),
+    );
+    // A -> B -> C, A -> B2 -> C, A -> A2
+    let dependencies = dependencies(&[
+        (&a, &b, 1),
+        (&b, &c, 1),
+        (&a, &b2, 1),
+        (&b2, &c, 1),
+        (&a, &a2, 1),
+    ]);
+    let metrics = CouplingMetrics::from_endpoint_dependencies(&dependencies);
+
+    let services = metrics
+        .services
+        .iter()
+        .map(|s| {
+            let c = &s.coupling;
+            (s.service.as_str(), c.fan_in, c.fan_out, c.ais, c.ads, c.acs)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        services,
+        vec![
+            ("svc-a", 0, 1, 1, 1, 1),
+            ("svc-b", 1, 1, 1, 1, 1),
+            ("svc-c", 1, 0, 1, 0, 0),
+        ]
+    );
+    assert_eq!(metrics.services[0].coupling.instability, 1.0);
+    assert_eq!(metrics.services[1].coupling.instability, 0.5);
+
+    let c = metrics
+        .endpoints
+        .iter()
+        .find(|e| e.unique_endpoint_name.ends_with("/c"))
+        .unwrap();
+    assert_eq!((c.coupling.fan_in, c.coupling.ais), (2, 1));
+    // the call within svc-a is left out
+    let a2 = metrics
+        .endpoints
+        .iter()
+        .find(|e| e.unique_endpoint_name.ends_with("/a2"))
+        .unwrap();
+    assert_eq!((a2.coupling.fan_in, a2.coupling.ais), (0, 0));
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::url_matcher::UrlMatcher;
+
+use super::{endpoint_info::EndpointInfo, span_tree::SpanTree, trace::Trace};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointCriticalPath {
+    // the entry endpoint (topmost SERVER span) of the traces
+    pub endpoint: EndpointInfo,
+    pub traces: usize,
+    // sum of the entry span durations, in microseconds
+    pub total_latency: u64,
+    pub path: Vec<CriticalPathItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CriticalPathItem {
+    pub endpoint: EndpointInfo,
+    // number of traces having this endpoint on the critical path
+    pub occurrences: usize,
+    // time spent on the critical path, in microseconds
+    pub contribution: u64,
+    // occurrences / traces
+    pub frequency: f64,
+    // contribution / total_latency
+    pub ratio: f64,
+}
+
+#[derive(Debug)]
+struct CriticalPathAccumulator {
+    endpoint: EndpointInfo,
+    traces: usize,
+    total_latency: u64,
+    path: HashMap<String, (EndpointInfo, usize, u64)>,
+}
+
+impl EndpointCriticalPath {
+    pub fn from_traces(
+        traces: &[Vec<Trace>],
+        url_matcher: &UrlMatcher,
+    ) -> Vec<EndpointCriticalPath> {
+        let mut accumulators: HashMap<String, CriticalPathAccumulator> = HashMap::new();
+
+        for tree in SpanTree::from_traces(traces).iter() {
+            let nodes = &tree.nodes;
+            for entry in (0..nodes.len()).filter(|&i| {
+                // the critical path of a trace is counted once, when its entry span is new
+                nodes[i].span.kind == *"SERVER"
+                    && !nodes[i].span.processed
+                    && !tree.ancestors(i).any(|p| nodes[p].span.kind == *"SERVER")
+            }) {
+                let mut contributions = HashMap::new();
+                let node = &nodes[entry];
+                Self::walk(
+                    tree,
+                    entry,
+                    node.span,
+                    (node.timestamp, node.timestamp + node.span.duration),
+                    &mut contributions,
+                );
+
+                let mut per_endpoint: HashMap<String, (EndpointInfo, u64)> = HashMap::new();
+                for (owner, time) in contributions.into_values() {
+                    let Some(info) = owner.to_endpoint_info(url_matcher) else {
+                        continue;
+                    };
+                    per_endpoint
+                        .entry(info.unique_endpoint_name.clone())
+                        .or_insert((info, 0))
+                        .1 += time;
+                }
+
+                let Some(info) = node.span.to_endpoint_info(url_matcher) else {
+                    continue;
+                };
+                let accumulator = accumulators
+                    .entry(info.unique_endpoint_name.clone())
+                    .or_insert_with(|| CriticalPathAccumulator {
+                        endpoint: info,
+                        traces: 0,
+                        total_latency: 0,
+                        path: HashMap::new(),
+                    });
+                accumulator.traces += 1;
+                accumulator.total_latency += node.span.duration;
+                for (name, (info, time)) in per_endpoint.into_iter() {
+                    let item = accumulator.path.entry(name).or_insert((info, 0, 0));
+                    item.1 += 1;
+                    item.2 += time;
+                }
+            }
+        }
+
+        let mut critical_paths = accumulators
+            .into_values()
+            .map(|acc| {
+                let mut path = acc
+                    .path
+                    .into_values()
+                    .map(|(endpoint, occurrences, contribution)| CriticalPathItem {
+                        endpoint,
+                        occurrences,
+                        contribution,
+                        frequency: occurrences as f64 / acc.traces as f64,
+                        ratio: if acc.total_latency == 0 {
+                            0.0
+                        } else {
+                            contribution as f64 / acc.total_latency as f64
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                path.sort_by(|a, b| {
+                    b.contribution.cmp(&a.contribution).then_with(|| {
+                        a.endpoint
+                            .unique_endpoint_name
+                            .cmp(&b.endpoint.unique_endpoint_name)
+                    })
+                });
+                EndpointCriticalPath {
+                    endpoint: acc.endpoint,
+                    traces: acc.traces,
+                    total_latency: acc.total_latency,
+                    path,
+                }
+            })
+            .collect::<Vec<_>>();
+        critical_paths.sort_by(|a, b| {
+            a.endpoint
+                .unique_endpoint_name
+                .cmp(&b.endpoint.unique_endpoint_name)
+        });
+        critical_paths
+    }
+
+    /// Walks the span backwards from the end of its (clamped) interval, always following the
+    /// child that finished last before the cursor. Time not covered by a child on the path is
+    /// self time, which is credited to the nearest SERVER span (CLIENT spans are the caller waiting).
+    fn walk<'a>(
+        tree: &SpanTree<'a>,
+        i: usize,
+        owner: &'a Trace,
+        (lower, upper): (u64, u64),
+        contributions: &mut HashMap<&'a str, (&'a Trace, u64)>,
+    ) {
+        let node = &tree.nodes[i];
+        let owner = if node.span.kind == *"SERVER" {
+            node.span
+        } else {
+            owner
+        };
+        // clamp into the parent interval, child spans can still exceed it after skew correction
+        let start = node.timestamp.max(lower);
+        let end = (node.timestamp + node.span.duration).min(upper);
+        if end <= start {
+            return;
+        }
+
+        let mut cursor = end;
+        let mut self_time = 0;
+        let mut sorted = node.children.clone();
+        sorted.sort_by_key(|&c| {
+            std::cmp::Reverse(tree.nodes[c].timestamp + tree.nodes[c].span.duration)
+        });
+        for c in sorted {
+            let child = &tree.nodes[c];
+            let child_start = child.timestamp.max(start);
+            let child_end = (child.timestamp + child.span.duration).min(cursor);
+            if child_end <= child_start {
+                continue;
+            }
+            self_time += cursor - child_end;
+            Self::walk(tree, c, owner, (child_start, child_end), contributions);
+            cursor = child_start;
+            if cursor <= start {
+                break;
+            }
+        }
+        self_time += cursor - start;
+        contributions
+            .entry(owner.id.as_str())
+            .or_insert((owner, 0))
+            .1 += self_time;
+    }
+}
+
+#[test]
+fn test_critical_path() {
+    use crate::data::fixtures::{client, server, URL_A, URL_B, URL_C};
+    // A calls B and C in parallel, C finishes last and overlaps B
+    let trace = vec![
+        server("1", None, URL_A, 0, 100),
+        client("2", Some("1"), URL_B, 10, 50),
+        server("3", Some("2"), URL_B, 12, 45),
+        client("4", Some("1"), URL_C, 20, 60),
+        server("5", Some("4"), URL_C, 25, 50),
+    ];
+    let matcher = UrlMatcher::new();
+    let paths = EndpointCriticalPath::from_traces(&[trace], &matcher);
+    assert_eq!(paths.len(), 1);
+
+    let path = &paths[0];
+    assert_eq!(path.traces, 1);
+    assert_eq!(path.total_latency, 100);
+    let contributions = path
+        .path
+        .iter()
+        .map(|p| (p.endpoint.service.as_str(), p.contribution))
+        .collect::<Vec<_>>();
+    // A: [0,12) + [20,25) + [75,100), B: [12,20) (overlapped by C), C: [25,75)
+    assert_eq!(
+        contributions,
+        vec![("svc-c", 50), ("svc-a", 42), ("svc-b", 8)]
+    );
+    assert_eq!(path.path.iter().map(|p| p.contribution).sum::<u64>(), 100);
+}
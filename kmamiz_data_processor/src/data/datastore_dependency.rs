@@ -0,0 +1,398 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::url_matcher::UrlMatcher;
+
+use super::{endpoint_dependency::EndpointDependencyMetrics, span_tree::SpanTree, trace::Trace};
+
+/// A database or cache reached by CLIENT spans carrying `db.*` tags.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DatastoreDependency {
+    // "{system}\t{name}"
+    pub unique_datastore_name: String,
+    // mongodb, redis, postgresql, ...
+    pub system: String,
+    pub name: String,
+    pub operations: Vec<DatastoreOperation>,
+    pub callers: Vec<DatastoreCaller>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DatastoreCaller {
+    pub unique_service_name: String,
+    // None if the call was not made while serving a request, e.g. from a message consumer
+    pub unique_endpoint_name: Option<String>,
+    pub operations: Vec<DatastoreOperation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DatastoreOperation {
+    // SELECT, find, GET, ...
+    pub operation: String,
+    // collection or table
+    pub target: Option<String>,
+    // literals replaced with ?
+    pub statement: Option<String>,
+    pub metrics: EndpointDependencyMetrics,
+}
+
+type OperationKey = (String, Option<String>, Option<String>);
+
+#[derive(Debug, Default)]
+struct DatastoreAccumulator {
+    operations: BTreeMap<OperationKey, EndpointDependencyMetrics>,
+    // (uniqueServiceName, uniqueEndpointName)
+    callers: BTreeMap<(String, Option<String>), BTreeMap<OperationKey, EndpointDependencyMetrics>>,
+}
+
+impl DatastoreDependency {
+    pub fn from_traces(
+        traces: &[Vec<Trace>],
+        url_matcher: &UrlMatcher,
+    ) -> Vec<DatastoreDependency> {
+        let mut datastores: BTreeMap<(String, String), DatastoreAccumulator> = BTreeMap::new();
+
+        for tree in SpanTree::from_traces(traces).iter() {
+            for (i, node) in tree.nodes.iter().enumerate() {
+                let span = node.span;
+                if span.kind != *"CLIENT" || span.processed {
+                    continue;
+                }
+                let tags = &span.tags;
+                let Some(system) = tags.db_system.clone() else {
+                    continue;
+                };
+                let statement = tags
+                    .db_statement
+                    .as_deref()
+                    .map(|s| normalize_statement(&system, s));
+                let operation = tags
+                    .db_operation
+                    .clone()
+                    .or_else(|| statement.as_deref().and_then(|s| operation_of(&system, s)))
+                    .unwrap_or_else(|| span.name.clone());
+                let target = tags
+                    .db_mongodb_collection
+                    .clone()
+                    .or_else(|| tags.db_sql_table.clone())
+                    .or_else(|| statement.as_deref().and_then(|s| target_of(&system, s)));
+                let key = (operation, target, statement);
+                let metrics = EndpointDependencyMetrics::from_span(span);
+
+                let caller = match Self::serving_span(tree, i)
+                    .and_then(|server| server.to_endpoint_info(url_matcher))
+                {
+                    Some(info) => (info.unique_service_name, Some(info.unique_endpoint_name)),
+                    None => {
+                        let (service, namespace, version) = span.to_service_info();
+                        (format!("{service}\t{namespace}\t{version}"), None)
+                    }
+                };
+
+                // caches usually have no database name, the address tells them apart
+                let name = tags
+                    .db_name
+                    .clone()
+                    .or_else(|| tags.server_address.clone())
+                    .or_else(|| tags.peer_address.clone())
+                    .unwrap_or_else(|| "unknown".to_owned());
+                let datastore = datastores.entry((system, name)).or_default();
+                Self::add(&mut datastore.operations, key.clone(), &metrics);
+                Self::add(datastore.callers.entry(caller).or_default(), key, &metrics);
+            }
+        }
+
+        datastores
+            .into_iter()
+            .map(|((system, name), acc)| DatastoreDependency {
+                unique_datastore_name: format!("{system}\t{name}"),
+                system,
+                name,
+                operations: Self::to_operations(acc.operations),
+                callers: acc
+                    .callers
+                    .into_iter()
+                    .map(
+                        |((unique_service_name, unique_endpoint_name), ops)| DatastoreCaller {
+                            unique_service_name,
+                            unique_endpoint_name,
+                            operations: Self::to_operations(ops),
+                        },
+                    )
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn add(
+        operations: &mut BTreeMap<OperationKey, EndpointDependencyMetrics>,
+        key: OperationKey,
+        metrics: &EndpointDependencyMetrics,
+    ) {
+        match operations.get_mut(&key) {
+            Some(existing) => existing.merge(metrics),
+            None => {
+                operations.insert(key, metrics.clone());
+            }
+        }
+    }
+
+    fn to_operations(
+        operations: BTreeMap<OperationKey, EndpointDependencyMetrics>,
+    ) -> Vec<DatastoreOperation> {
+        operations
+            .into_iter()
+            .map(
+                |((operation, target, statement), metrics)| DatastoreOperation {
+                    operation,
+                    target,
+                    statement,
+                    metrics,
+                },
+            )
+            .collect()
+    }
+
+    /// The SERVER span the call was made for, local and CLIENT spans in between are skipped.
+    fn serving_span<'a>(tree: &SpanTree<'a>, i: usize) -> Option<&'a Trace> {
+        tree.ancestors(i)
+            .map(|p| tree.nodes[p].span)
+            .find(|s| s.kind != *"CLIENT" && !s.kind.is_empty())
+            .filter(|s| s.kind == *"SERVER")
+    }
+}
+
+struct StatementMatchers {
+    quoted: Regex,
+    json_value: Regex,
+    number: Regex,
+    in_list: Regex,
+    whitespace: Regex,
+    sql_target: Regex,
+    mongo_target: Regex,
+    keyword: Regex,
+    word: Regex,
+}
+
+fn matchers() -> &'static StatementMatchers {
+    static MATCHERS: OnceLock<StatementMatchers> = OnceLock::new();
+    MATCHERS.get_or_init(|| StatementMatchers {
+        quoted: Regex::new(r"'(?:[^']|'')*'").unwrap(),
+        json_value: Regex::new(r#":\s*(?:"(?:[^"\\]|\\.)*"|true|false|null)"#).unwrap(),
+        number: Regex::new(r"-?\b\d+(?:\.\d+)?\b").unwrap(),
+        in_list: Regex::new(r"(?i)\b(IN|VALUES)\s*\(\s*\?(?:\s*,\s*\?)*\s*\)").unwrap(),
+        whitespace: Regex::new(r"\s+").unwrap(),
+        sql_target: Regex::new(r#"(?i)\b(?:FROM|INTO|UPDATE|JOIN|TABLE)\s+[`"]?([\w.]+)"#).unwrap(),
+        mongo_target: Regex::new(r#"^\{\s*"\w+"\s*:\s*"([^"]+)""#).unwrap(),
+        keyword: Regex::new(r"[A-Za-z_]+").unwrap(),
+        word: Regex::new(r"\b[A-Za-z]+\b").unwrap(),
+    })
+}
+
+static SQL_KEYWORDS: &[&str] = &[
+    "ALL",
+    "AND",
+    "AS",
+    "ASC",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "CROSS",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "ELSE",
+    "END",
+    "EXISTS",
+    "FOR",
+    "FROM",
+    "FULL",
+    "GROUP",
+    "HAVING",
+    "ILIKE",
+    "IN",
+    "INNER",
+    "INSERT",
+    "INTO",
+    "IS",
+    "JOIN",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "RETURNING",
+    "RIGHT",
+    "SELECT",
+    "SET",
+    "THEN",
+    "UNION",
+    "UPDATE",
+    "VALUES",
+    "WHEN",
+    "WHERE",
+    "WITH",
+];
+
+/// Strips literals from a statement so that statements differing only in values are grouped together.
+/// SQL keywords are uppercased, identifiers keep their case.
+pub fn normalize_statement(system: &str, statement: &str) -> String {
+    let m = matchers();
+    if system == "redis" {
+        // the command is kept, keys and values are all literals
+        let mut tokens = statement.split_whitespace();
+        let command = tokens.next().unwrap_or_default().to_uppercase();
+        return tokens.fold(command, |s, _| s + " ?");
+    }
+
+    let mut normalized = if system == "mongodb" {
+        // keep the first value, it names the collection
+        let target = m.mongo_target.find(statement).map(|t| t.end()).unwrap_or(0);
+        let (head, rest) = statement.split_at(target);
+        format!("{head}{}", m.json_value.replace_all(rest, ": ?"))
+    } else {
+        let unquoted = m.quoted.replace_all(statement, "?");
+        m.word
+            .replace_all(&unquoted, |c: &regex::Captures| {
+                let word = c[0].to_uppercase();
+                if SQL_KEYWORDS.contains(&word.as_str()) {
+                    word
+                } else {
+                    c[0].to_owned()
+                }
+            })
+            .to_string()
+    };
+    normalized = m.number.replace_all(&normalized, "?").to_string();
+    normalized = m.in_list.replace_all(&normalized, "$1 (?)").to_string();
+    m.whitespace.replace_all(normalized.trim(), " ").to_string()
+}
+
+fn operation_of(system: &str, statement: &str) -> Option<String> {
+    let keyword = matchers().keyword.find(statement)?.as_str();
+    if system == "mongodb" {
+        Some(keyword.to_owned())
+    } else {
+        Some(keyword.to_uppercase())
+    }
+}
+
+fn target_of(system: &str, statement: &str) -> Option<String> {
+    let m = matchers();
+    let matcher = match system {
+        "redis" => return None,
+        "mongodb" => &m.mongo_target,
+        _ => &m.sql_target,
+    };
+    matcher
+        .captures(statement)
+        .and_then(|c| c.get(1))
+        .map(|t| t.as_str().to_owned())
+}
+
+#[test]
+fn test_normalize_statement() {
+    assert_eq!(
+        normalize_statement(
+            "postgresql",
+            "SELECT *  FROM users WHERE id = 42 AND name = 'O''Brien' AND role IN (1, 2, 3)"
+        ),
+        "SELECT * FROM users WHERE id = ? AND name = ? AND role IN (?)"
+    );
+    assert_eq!(
+        normalize_statement("mysql", "select Name from Users where note = 'from'"),
+        "SELECT Name FROM Users WHERE note = ?"
+    );
+    assert_eq!(
+        normalize_statement(
+            "mongodb",
+            r#"{"find": "users", "filter": {"age": {"$gt": 30}, "name": "a"}}"#
+        ),
+        r#"{"find": "users", "filter": {"age": {"$gt": ?}, "name": ?}}"#
+    );
+    assert_eq!(
+        normalize_statement("redis", "set session:1 abc EX 60"),
+        "SET ? ? ? ?"
+    );
+}
+
+#[test]
+fn test_datastore_dependencies() {
+    use crate::data::fixtures::{client, server, span, URL_A};
+    let db = |id: &str, parent_id: &str, system: &str, statement: &str, duration: u64| {
+        let mut span = client(id, Some(parent_id), "", 0, duration);
+        span.tags = Default::default();
+        span.tags.db_system = Some(system.to_owned());
+        span.tags.db_statement = Some(statement.to_owned());
+        if system != "redis" {
+            span.tags.db_name = Some("shop".to_owned());
+        }
+        span.local_endpoint.service_name = "svc-a".to_owned();
+        span
+    };
+    let mut cache = db("6", "1", "redis", "GET cart:2", 2);
+    cache.tags.server_address = Some("redis.cache".to_owned());
+    let mut local = span("", "2", Some("1"), "", 0, 100);
+    local.tags = Default::default();
+    let traces = vec![vec![
+        server("1", None, URL_A, 0, 100),
+        local,
+        db(
+            "3",
+            "2",
+            "postgresql",
+            "SELECT * FROM orders WHERE id = 1",
+            10,
+        ),
+        db(
+            "4",
+            "2",
+            "postgresql",
+            "select * from orders where id = 2",
+            30,
+        ),
+        db("5", "1", "redis", "GET cart:1", 2),
+        cache,
+    ]];
+
+    let datastores = DatastoreDependency::from_traces(&traces, &UrlMatcher::new());
+    let names = datastores
+        .iter()
+        .map(|d| d.unique_datastore_name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec!["postgresql\tshop", "redis\tredis.cache", "redis\tunknown"]
+    );
+
+    let postgres = &datastores[0];
+    assert_eq!(postgres.operations.len(), 1);
+    let select = &postgres.operations[0];
+    assert_eq!(select.operation, "SELECT");
+    assert_eq!(select.target.as_deref(), Some("orders"));
+    assert_eq!(
+        select.statement.as_deref(),
+        Some("SELECT * FROM orders WHERE id = ?")
+    );
+    assert_eq!(select.metrics.calls, 2);
+    assert_eq!(postgres.callers.len(), 1);
+    assert_eq!(
+        postgres.callers[0].unique_endpoint_name.as_deref(),
+        Some("svc-a\tns\tlatest\tGET\thttp://svc-a.ns.svc.cluster.local/a")
+    );
+
+    let redis = &datastores[2];
+    assert_eq!(redis.operations[0].operation, "GET");
+    assert_eq!(redis.operations[0].target, None);
+    assert_eq!(redis.operations[0].metrics.latency_total, 2);
+}
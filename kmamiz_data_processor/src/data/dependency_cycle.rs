@@ -0,0 +1,276 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::http_client::url_matcher::UrlMatcher;
+
+use super::{endpoint_dependency::EndpointDependency, span_tree::SpanTree, trace::Trace};
+
+// example trace IDs kept per cycle
+const MAX_EXAMPLES: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyCycles {
+    pub endpoints: Vec<DependencyCycle>,
+    pub services: Vec<DependencyCycle>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyCycle {
+    // uniqueEndpointName or uniqueServiceName of the strongly connected members
+    pub members: Vec<String>,
+    pub trace_ids: Vec<String>,
+}
+
+impl DependencyCycles {
+    pub fn detect(
+        dependencies: &[EndpointDependency],
+        traces: &[Vec<Trace>],
+        url_matcher: &UrlMatcher,
+    ) -> DependencyCycles {
+        let mut endpoint_edges = BTreeSet::new();
+        let mut service_edges = BTreeSet::new();
+        for dep in dependencies.iter() {
+            for item in dep.depending_on.iter().filter(|d| d.distance == 1) {
+                endpoint_edges.insert((
+                    dep.endpoint.unique_endpoint_name.as_str(),
+                    item.endpoint.unique_endpoint_name.as_str(),
+                ));
+                // a service calling itself is not a circular dependency between services
+                if dep.endpoint.unique_service_name != item.endpoint.unique_service_name {
+                    service_edges.insert((
+                        dep.endpoint.unique_service_name.as_str(),
+                        item.endpoint.unique_service_name.as_str(),
+                    ));
+                }
+            }
+        }
+
+        let mut endpoints = Self::to_cycles(Self::strongly_connected(&endpoint_edges));
+        let mut services = Self::to_cycles(Self::strongly_connected(&service_edges));
+        if endpoints.is_empty() && services.is_empty() {
+            return DependencyCycles {
+                endpoints,
+                services,
+            };
+        }
+
+        let endpoint_index = Self::member_index(&endpoints);
+        let service_index = Self::member_index(&services);
+        for tree in SpanTree::from_traces(traces).iter() {
+            for (caller, callee) in Self::server_calls(tree) {
+                let (Some(caller), Some(callee)) = (
+                    caller.to_endpoint_info(url_matcher),
+                    callee.to_endpoint_info(url_matcher),
+                ) else {
+                    continue;
+                };
+                let trace_id = tree.trace_id;
+                if let Some(i) = Self::same_cycle(
+                    &endpoint_index,
+                    &caller.unique_endpoint_name,
+                    &callee.unique_endpoint_name,
+                ) {
+                    Self::add_example(&mut endpoints[i], trace_id);
+                }
+                if let Some(i) = Self::same_cycle(
+                    &service_index,
+                    &caller.unique_service_name,
+                    &callee.unique_service_name,
+                ) {
+                    Self::add_example(&mut services[i], trace_id);
+                }
+            }
+        }
+
+        DependencyCycles {
+            endpoints,
+            services,
+        }
+    }
+
+    /// Tarjan's algorithm, iterative to survive deep graphs.
+    /// Returns components with more than one member, or a single member calling itself.
+    fn strongly_connected(edges: &BTreeSet<(&str, &str)>) -> Vec<Vec<String>> {
+        let mut nodes: Vec<&str> = vec![];
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        for (from, to) in edges.iter() {
+            for n in [from, to] {
+                if !ids.contains_key(n) {
+                    ids.insert(n, nodes.len());
+                    nodes.push(n);
+                }
+            }
+        }
+        let mut adjacency = vec![vec![]; nodes.len()];
+        for (from, to) in edges.iter() {
+            adjacency[ids[from]].push(ids[to]);
+        }
+
+        let mut index = vec![None; nodes.len()];
+        let mut low = vec![0; nodes.len()];
+        let mut on_stack = vec![false; nodes.len()];
+        let mut stack = vec![];
+        let mut counter = 0;
+        let mut components = vec![];
+
+        for root in 0..nodes.len() {
+            if index[root].is_some() {
+                continue;
+            }
+            // (node, next neighbour to visit)
+            let mut call_stack = vec![(root, 0)];
+            index[root] = Some(counter);
+            low[root] = counter;
+            counter += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some((node, next)) = call_stack.pop() {
+                if let Some(&neighbour) = adjacency[node].get(next) {
+                    call_stack.push((node, next + 1));
+                    match index[neighbour] {
+                        None => {
+                            index[neighbour] = Some(counter);
+                            low[neighbour] = counter;
+                            counter += 1;
+                            stack.push(neighbour);
+                            on_stack[neighbour] = true;
+                            call_stack.push((neighbour, 0));
+                        }
+                        Some(i) if on_stack[neighbour] => low[node] = low[node].min(i),
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                if let Some(&(parent, _)) = call_stack.last() {
+                    low[parent] = low[parent].min(low[node]);
+                }
+                if Some(low[node]) == index[node] {
+                    let mut component = vec![];
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node {
+                            break;
+                        }
+                    }
+                    let is_cycle = component.len() > 1 || adjacency[node].contains(&node);
+                    if is_cycle {
+                        components
+                            .push(component.into_iter().map(|m| nodes[m].to_owned()).collect());
+                    }
+                }
+            }
+        }
+        components
+    }
+
+    fn to_cycles(components: Vec<Vec<String>>) -> Vec<DependencyCycle> {
+        let mut cycles = components
+            .into_iter()
+            .map(|mut members| {
+                members.sort();
+                DependencyCycle {
+                    members,
+                    trace_ids: vec![],
+                }
+            })
+            .collect::<Vec<_>>();
+        cycles.sort_by(|a, b| a.members.cmp(&b.members));
+        cycles
+    }
+
+    fn member_index(cycles: &[DependencyCycle]) -> HashMap<String, usize> {
+        cycles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| c.members.iter().map(move |m| (m.clone(), i)))
+            .collect()
+    }
+
+    fn same_cycle(index: &HashMap<String, usize>, caller: &str, callee: &str) -> Option<usize> {
+        match (index.get(caller), index.get(callee)) {
+            (Some(a), Some(b)) if a == b => Some(*a),
+            _ => None,
+        }
+    }
+
+    fn add_example(cycle: &mut DependencyCycle, trace_id: &str) {
+        if cycle.trace_ids.len() >= MAX_EXAMPLES || cycle.trace_ids.iter().any(|t| t == trace_id) {
+            return;
+        }
+        cycle.trace_ids.push(trace_id.to_owned());
+        cycle.trace_ids.sort();
+    }
+
+    /// Direct SERVER to SERVER calls in a trace, CLIENT spans in between are skipped.
+    fn server_calls<'a>(tree: &SpanTree<'a>) -> Vec<(&'a Trace, &'a Trace)> {
+        let nodes = &tree.nodes;
+        let mut calls = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            if node.span.kind != *"SERVER" {
+                continue;
+            }
+            let caller = tree
+                .ancestors(i)
+                .map(|p| nodes[p].span)
+                .find(|s| s.kind != *"CLIENT")
+                .filter(|s| s.kind == *"SERVER");
+            if let Some(caller) = caller {
+                calls.push((caller, node.span));
+            }
+        }
+        calls
+    }
+}
+
+#[test]
+fn test_detect_cycles() {
+    use crate::data::fixtures::{dependencies, endpoint, server, URL_A, URL_B, URL_B2, URL_C};
+
+    let (a, b, b2, c) = (
+        endpoint(URL_A),
+        endpoint(URL_B),
+        endpoint(URL_B2),
+        endpoint(URL_C),
+    );
+    // A -> B -> A, B2 -> C
+    let dependencies = dependencies(&[(&a, &b, 1), (&b, &a, 1), (&b2, &c, 1)]);
+    let mut looping = vec![
+        server("1", None, URL_A, 0, 100),
+        server("2", Some("1"), URL_B, 10, 80),
+        server("3", Some("2"), URL_A, 20, 50),
+    ];
+    looping
+        .iter_mut()
+        .for_each(|s| s.trace_id = "loop".to_owned());
+    let traces = vec![looping];
+    let matcher = UrlMatcher::new();
+
+    let cycles = DependencyCycles::detect(&dependencies, &traces, &matcher);
+    assert_eq!(cycles.endpoints.len(), 1);
+    assert_eq!(cycles.endpoints[0].members.len(), 2);
+    assert_eq!(cycles.endpoints[0].trace_ids, vec!["loop"]);
+    assert_eq!(
+        cycles.services,
+        vec![DependencyCycle {
+            members: vec![
+                "svc-a\tns\tlatest".to_owned(),
+                "svc-b\tns\tlatest".to_owned()
+            ],
+            trace_ids: vec!["loop".to_owned()],
+        }]
+    );
+
+    // parent chains looping back on themselves must terminate
+    let cyclic = vec![
+        server("6", Some("7"), URL_A, 0, 100),
+        server("7", Some("6"), URL_B, 10, 50),
+    ];
+    let dependencies = Trace::to_endpoint_dependencies(&[cyclic], &matcher);
+    assert_eq!(dependencies.len(), 2);
+}
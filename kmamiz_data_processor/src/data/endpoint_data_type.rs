@@ -1,5 +1,8 @@
-use super::request_type::RequestType;
+use std::collections::BTreeMap;
+
+use super::{body_statistics::BodyStatistics, request_type::RequestType};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -22,15 +25,72 @@ pub struct EndpointDataSchema {
     pub status: String,
     pub request_sample: Option<String>,
     pub request_schema: Option<String>,
+    // JSON Schema (draft 2020-12) of the same inference as the TypeScript types
+    pub request_json_schema: Option<Value>,
     pub request_content_type: Option<String>,
+    pub request_statistics: Option<BodyStatistics>,
     pub request_params: Option<Vec<EndpointRequestParams>>,
     pub response_sample: Option<String>,
     pub response_schema: Option<String>,
+    pub response_json_schema: Option<Value>,
     pub response_content_type: Option<String>,
+    pub response_statistics: Option<BodyStatistics>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EndpointRequestParams {
-    param: String,
-    r#type: String,
+    pub param: String,
+    pub r#type: String,
+}
+
+impl EndpointDataType {
+    /// Latest schema of every (uniqueEndpointName, status).
+    pub fn latest_schemas(
+        datatypes: &[EndpointDataType],
+    ) -> BTreeMap<(&str, &str), &EndpointDataSchema> {
+        let mut latest: BTreeMap<(&str, &str), &EndpointDataSchema> = BTreeMap::new();
+        for datatype in datatypes.iter() {
+            for schema in datatype.schemas.iter() {
+                let key = (
+                    datatype.unique_endpoint_name.as_str(),
+                    schema.status.as_str(),
+                );
+                match latest.get(&key) {
+                    Some(s) if s.time > schema.time => {}
+                    _ => {
+                        latest.insert(key, schema);
+                    }
+                }
+            }
+        }
+        latest
+    }
+
+    /// Adds the statistics of the latest stored schema with the same endpoint and status,
+    /// so that the statistics cover every processed window.
+    pub fn accumulate_statistics(
+        datatypes: &mut [EndpointDataType],
+        existing: &[EndpointDataType],
+    ) {
+        let stored = Self::latest_schemas(existing);
+        for datatype in datatypes.iter_mut() {
+            for schema in datatype.schemas.iter_mut() {
+                let key = (
+                    datatype.unique_endpoint_name.as_str(),
+                    schema.status.as_str(),
+                );
+                let Some(old) = stored.get(&key) else {
+                    continue;
+                };
+                for (statistics, old) in [
+                    (&mut schema.request_statistics, &old.request_statistics),
+                    (&mut schema.response_statistics, &old.response_statistics),
+                ] {
+                    if let (Some(statistics), Some(old)) = (statistics.as_mut(), old) {
+                        statistics.merge(old);
+                    }
+                }
+            }
+        }
+    }
 }
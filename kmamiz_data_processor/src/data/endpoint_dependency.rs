@@ -1,14 +1,13 @@
-use std::{
-    collections::{HashMap, HashSet},
-    error::Error,
-    fmt::Display,
-    str::FromStr,
-};
-
-use super::endpoint_info::EndpointInfo;
+use std::{collections::BTreeMap, error::Error, fmt::Display, str::FromStr};
+
+use super::{endpoint_info::EndpointInfo, trace::Trace};
 use serde::{de, Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Debug, Clone)]
+// log2 scaled latency buckets, 4 buckets per power of 2
+const HISTOGRAM_SCALE: f64 = 4.0;
+const HISTOGRAM_BUCKETS: usize = 128;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum EndpointDependencyType {
     Client,
     Server,
@@ -44,96 +43,515 @@ impl<'de> Deserialize<'de> for EndpointDependencyType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct EndpointDependency {
     pub _id: Option<String>,
     pub endpoint: EndpointInfo,
     pub depending_on: Vec<EndpointDependencyItem>,
     pub depending_by: Vec<EndpointDependencyItem>,
+    // span timestamp in microseconds, None for dependencies recorded before it was tracked
+    pub last_seen: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct EndpointDependencyItem {
     pub endpoint: EndpointInfo,
     pub distance: u32,
     pub r#type: EndpointDependencyType,
+    // only recorded on direct (distance 1) dependencies
+    pub metrics: Option<EndpointDependencyMetrics>,
+    pub last_seen: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointDependencyMetrics {
+    pub calls: u64,
+    pub request_errors: u64,
+    pub server_errors: u64,
+    // latencies are measured from the CLIENT span, in microseconds
+    pub latency_total: u64,
+    pub latency_mean: f64,
+    pub latency_p50: u64,
+    pub latency_p95: u64,
+    pub latency_p99: u64,
+    pub latency_histogram: Vec<u64>,
+    // span timestamps, in microseconds
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Identifies a dependency of an endpoint. The distance is not a part of the key,
+/// only the shortest distance observed between two endpoints is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct DependencyKey {
+    r#type: EndpointDependencyType,
+    unique_endpoint_name: String,
 }
 
 #[derive(Debug, Clone)]
 struct DependencyMapping {
-    endpoint: EndpointDependency,
-    depending_by: HashSet<String>,
-    depending_on: HashSet<String>,
+    _id: Option<String>,
+    endpoint: EndpointInfo,
+    last_seen: Option<u64>,
+    dependencies: BTreeMap<DependencyKey, EndpointDependencyItem>,
 }
 
 impl EndpointDependency {
+    /// Merges dependencies by endpoint, the result does not depend on the order of the inputs.
+    /// The smallest `_id` and the endpoint info seen last are kept. Edge metrics are added up,
+    /// so combining a window with itself counts its calls twice.
     pub fn combine(
         dep1: Vec<EndpointDependency>,
         dep2: Vec<EndpointDependency>,
     ) -> Vec<EndpointDependency> {
-        let mut dependency_mapping: HashMap<String, DependencyMapping> = HashMap::new();
-        dep1.into_iter().for_each(|dep| {
-            dependency_mapping.insert(dep.endpoint.unique_endpoint_name.clone(), dep.into());
-        });
-        dep2.into_iter().for_each(|dep| {
-            let entry = dependency_mapping.get_mut(&dep.endpoint.unique_endpoint_name);
-            if let Some(existing) = entry {
-                EndpointDependency::merge_dependencies(
-                    dep.depending_by,
-                    &mut existing.endpoint.depending_by,
-                    &mut existing.depending_by,
-                );
-                EndpointDependency::merge_dependencies(
-                    dep.depending_on,
-                    &mut existing.endpoint.depending_on,
-                    &mut existing.depending_on,
-                );
-            } else {
-                dependency_mapping.insert(dep.endpoint.unique_endpoint_name.clone(), dep.into());
+        let mut dependency_mapping: BTreeMap<String, DependencyMapping> = BTreeMap::new();
+        dep1.into_iter().chain(dep2).for_each(|dep| {
+            let mapping = dependency_mapping
+                .entry(dep.endpoint.unique_endpoint_name.clone())
+                .or_insert_with(|| DependencyMapping {
+                    _id: None,
+                    endpoint: dep.endpoint.clone(),
+                    last_seen: dep.last_seen,
+                    dependencies: BTreeMap::new(),
+                });
+            mapping._id = match (mapping._id.take(), dep._id) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            if (dep.last_seen, &dep.endpoint) > (mapping.last_seen, &mapping.endpoint) {
+                mapping.endpoint = dep.endpoint;
             }
+            mapping.last_seen = mapping.last_seen.max(dep.last_seen);
+            dep.depending_on
+                .into_iter()
+                .chain(dep.depending_by)
+                .for_each(|item| mapping.merge_item(item));
         });
 
-        dependency_mapping
-            .into_values()
-            .map(|dep| dep.endpoint)
+        dependency_mapping.into_values().map(|m| m.into()).collect()
+    }
+
+    /// Drops dependencies not observed within `retention` before `now` (both in microseconds),
+    /// endpoints are dropped as well once they have no dependencies left and are expired.
+    /// Dependencies without a timestamp are stamped with `now` and age from there.
+    pub fn expire(
+        dependencies: Vec<EndpointDependency>,
+        now: u64,
+        retention: u64,
+    ) -> Vec<EndpointDependency> {
+        let cutoff = now.saturating_sub(retention);
+        let is_alive = |last_seen: &mut Option<u64>| *last_seen.get_or_insert(now) >= cutoff;
+
+        dependencies
+            .into_iter()
+            .filter_map(|mut dep| {
+                dep.depending_on.retain_mut(|d| is_alive(&mut d.last_seen));
+                dep.depending_by.retain_mut(|d| is_alive(&mut d.last_seen));
+                let alive = is_alive(&mut dep.last_seen);
+                if alive || !dep.depending_on.is_empty() || !dep.depending_by.is_empty() {
+                    Some(dep)
+                } else {
+                    None
+                }
+            })
             .collect()
     }
+}
 
-    fn merge_dependencies(
-        from: Vec<EndpointDependencyItem>,
-        to: &mut Vec<EndpointDependencyItem>,
-        reference: &mut HashSet<String>,
-    ) {
-        from.into_iter().for_each(|d| {
-            let id = format!("{}\t{}", d.endpoint.unique_endpoint_name, d.distance);
-            if reference.contains(&id) {
-                return;
+impl DependencyMapping {
+    fn merge_item(&mut self, item: EndpointDependencyItem) {
+        let key = DependencyKey {
+            r#type: item.r#type.clone(),
+            unique_endpoint_name: item.endpoint.unique_endpoint_name.clone(),
+        };
+        match self.dependencies.get_mut(&key) {
+            Some(existing) => {
+                if (item.last_seen, &item.endpoint) > (existing.last_seen, &existing.endpoint) {
+                    existing.endpoint = item.endpoint;
+                }
+                existing.distance = existing.distance.min(item.distance);
+                existing.last_seen = existing.last_seen.max(item.last_seen);
+                existing.metrics =
+                    EndpointDependencyMetrics::merge_option(existing.metrics.take(), item.metrics);
             }
-            to.push(d);
-            reference.insert(id);
-        });
+            None => {
+                self.dependencies.insert(key, item);
+            }
+        }
     }
 }
 
-impl From<EndpointDependency> for DependencyMapping {
-    fn from(dep: EndpointDependency) -> Self {
-        let depending_by = dep
-            .depending_by
-            .iter()
-            .map(|dep| format!("{}\t{}", dep.endpoint.unique_endpoint_name, dep.distance))
-            .collect();
-        let depending_on = dep
-            .depending_by
-            .iter()
-            .map(|dep| format!("{}\t{}", dep.endpoint.unique_endpoint_name, dep.distance))
-            .collect();
+impl From<DependencyMapping> for EndpointDependency {
+    fn from(mapping: DependencyMapping) -> Self {
+        let (mut depending_on, mut depending_by): (Vec<_>, Vec<_>) = mapping
+            .dependencies
+            .into_values()
+            .partition(|item| item.r#type == EndpointDependencyType::Server);
+        let by_distance = |a: &EndpointDependencyItem, b: &EndpointDependencyItem| {
+            a.distance.cmp(&b.distance).then_with(|| {
+                a.endpoint
+                    .unique_endpoint_name
+                    .cmp(&b.endpoint.unique_endpoint_name)
+            })
+        };
+        depending_on.sort_by(by_distance);
+        depending_by.sort_by(by_distance);
 
-        DependencyMapping {
-            endpoint: dep,
-            depending_by,
+        EndpointDependency {
+            _id: mapping._id,
+            endpoint: mapping.endpoint,
             depending_on,
+            depending_by,
+            last_seen: mapping.last_seen,
+        }
+    }
+}
+
+impl EndpointDependencyMetrics {
+    pub fn from_span(span: &Trace) -> Self {
+        let status = &span.tags.http_status_code;
+        let mut metrics = EndpointDependencyMetrics {
+            calls: 1,
+            request_errors: status.starts_with('4') as u64,
+            server_errors: status.starts_with('5') as u64,
+            latency_total: span.duration,
+            latency_mean: span.duration as f64,
+            latency_p50: 0,
+            latency_p95: 0,
+            latency_p99: 0,
+            latency_histogram: vec![0; Self::bucket_of(span.duration) + 1],
+            first_seen: span.timestamp,
+            last_seen: span.timestamp,
+        };
+        metrics.latency_histogram[Self::bucket_of(span.duration)] = 1;
+        metrics.update_percentiles();
+        metrics
+    }
+
+    pub fn merge(&mut self, other: &EndpointDependencyMetrics) {
+        self.calls += other.calls;
+        self.latency_total += other.latency_total;
+        if self.calls > 0 {
+            self.latency_mean = self.latency_total as f64 / self.calls as f64;
+        }
+        self.request_errors += other.request_errors;
+        self.server_errors += other.server_errors;
+        if self.latency_histogram.len() < other.latency_histogram.len() {
+            self.latency_histogram
+                .resize(other.latency_histogram.len(), 0);
+        }
+        for (i, count) in other.latency_histogram.iter().enumerate() {
+            self.latency_histogram[i] += count;
         }
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.update_percentiles();
     }
+
+    pub fn merge_option(
+        a: Option<EndpointDependencyMetrics>,
+        b: Option<EndpointDependencyMetrics>,
+    ) -> Option<EndpointDependencyMetrics> {
+        match (a, b) {
+            (Some(mut a), Some(b)) => {
+                a.merge(&b);
+                Some(a)
+            }
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn bucket_of(latency: u64) -> usize {
+        if latency == 0 {
+            return 0;
+        }
+        let bucket = ((latency as f64).log2() * HISTOGRAM_SCALE).floor() as usize + 1;
+        bucket.min(HISTOGRAM_BUCKETS - 1)
+    }
+
+    fn upper_bound_of(bucket: usize) -> u64 {
+        if bucket == 0 {
+            return 0;
+        }
+        2f64.powf(bucket as f64 / HISTOGRAM_SCALE).ceil() as u64
+    }
+
+    fn percentile(&self, p: f64) -> u64 {
+        let total: u64 = self.latency_histogram.iter().sum();
+        let target = (total as f64 * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.latency_histogram.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Self::upper_bound_of(bucket);
+            }
+        }
+        0
+    }
+
+    fn update_percentiles(&mut self) {
+        self.latency_p50 = self.percentile(0.5);
+        self.latency_p95 = self.percentile(0.95);
+        self.latency_p99 = self.percentile(0.99);
+    }
+}
+
+#[test]
+fn test_edge_metrics() {
+    use crate::data::fixtures::{client, server, URL_A, URL_B};
+    use crate::http_client::url_matcher::UrlMatcher;
+
+    let mut failed_client = client("5", Some("4"), URL_B, 1_200, 400);
+    failed_client.tags.http_status_code = "503".to_owned();
+    let traces = vec![
+        vec![
+            server("1", None, URL_A, 1_000, 500),
+            client("2", Some("1"), URL_B, 1_100, 100),
+            server("3", Some("2"), URL_B, 1_110, 80),
+        ],
+        vec![
+            server("4", None, URL_A, 1_150, 500),
+            failed_client,
+            server("6", Some("5"), URL_B, 1_210, 300),
+        ],
+    ];
+    let dependencies = Trace::to_endpoint_dependencies(&traces, &UrlMatcher::new());
+    let combined = EndpointDependency::combine(dependencies, vec![]);
+    assert_eq!(combined.len(), 2);
+
+    let caller = combined
+        .iter()
+        .find(|d| d.endpoint.service == "svc-a")
+        .unwrap();
+    let callee = combined
+        .iter()
+        .find(|d| d.endpoint.service == "svc-b")
+        .unwrap();
+    assert_eq!(caller.depending_on.len(), 1);
+    assert_eq!(callee.depending_by.len(), 1);
+
+    let metrics = caller.depending_on[0].metrics.as_ref().unwrap();
+    assert_eq!(metrics, callee.depending_by[0].metrics.as_ref().unwrap());
+    assert_eq!(metrics.calls, 2);
+    assert_eq!(metrics.server_errors, 1);
+    assert_eq!(metrics.request_errors, 0);
+    assert_eq!(metrics.latency_mean, 250.0);
+    assert!(metrics.latency_p50 >= 100 && metrics.latency_p50 < 400);
+    assert!(metrics.latency_p99 >= 400);
+    assert_eq!(metrics.first_seen, 1_100);
+    assert_eq!(metrics.last_seen, 1_200);
+}
+
+#[cfg(test)]
+fn arb_dependencies() -> impl proptest::strategy::Strategy<Value = Vec<EndpointDependency>> {
+    use super::request_type::RequestType;
+    use crate::data::fixtures::client;
+    use proptest::prelude::*;
+
+    // the same endpoint may be recorded with a different label or request URL
+    let info = |(i, variant): (usize, usize)| EndpointInfo {
+        unique_service_name: format!("svc-{i}\tns\tlatest"),
+        unique_endpoint_name: format!("svc-{i}\tns\tlatest\tGET\thttp://svc-{i}/"),
+        label_name: (variant > 0).then(|| format!("/label-{variant}")),
+        service: format!("svc-{i}"),
+        namespace: "ns".to_owned(),
+        version: "latest".to_owned(),
+        url: format!("http://svc-{i}/?v={variant}"),
+        host: format!("svc-{i}"),
+        path: "/".to_owned(),
+        port: "80".to_owned(),
+        method: RequestType::Get,
+        cluster_name: "cluster.local".to_owned(),
+    };
+    let metrics = (1u64..10, 0u64..2000, 0u64..1000).prop_map(|(calls, latency, ts)| {
+        let span = client("0", None, "http://svc/", ts, latency);
+        let mut metrics = EndpointDependencyMetrics::from_span(&span);
+        for i in 1..calls {
+            let mut span = span.clone();
+            span.duration += i * 10;
+            metrics.merge(&EndpointDependencyMetrics::from_span(&span));
+        }
+        metrics
+    });
+    let item = (
+        (0usize..4, 0usize..3),
+        1u32..4,
+        any::<bool>(),
+        proptest::option::of(metrics),
+        proptest::option::of(0u64..1000),
+    )
+        .prop_map(
+            move |(i, distance, is_server, metrics, last_seen)| EndpointDependencyItem {
+                endpoint: info(i),
+                distance,
+                r#type: if is_server {
+                    EndpointDependencyType::Server
+                } else {
+                    EndpointDependencyType::Client
+                },
+                metrics: if distance == 1 { metrics } else { None },
+                last_seen,
+            },
+        );
+    let dependency = (
+        (0usize..4, 0usize..3),
+        proptest::option::of(0u32..3),
+        proptest::collection::vec(item, 0..6),
+        proptest::option::of(0u64..1000),
+    )
+        .prop_map(move |(i, id, items, last_seen)| {
+            let (depending_on, depending_by) = items
+                .into_iter()
+                .partition(|d| d.r#type == EndpointDependencyType::Server);
+            EndpointDependency {
+                _id: id.map(|id| format!("id-{id}")),
+                endpoint: info(i),
+                depending_on,
+                depending_by,
+                last_seen,
+            }
+        });
+    proptest::collection::vec(dependency, 0..6)
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_combine_associative(a in arb_dependencies(), b in arb_dependencies(), c in arb_dependencies()) {
+        let left = EndpointDependency::combine(EndpointDependency::combine(a.clone(), b.clone()), c.clone());
+        let right = EndpointDependency::combine(a, EndpointDependency::combine(b, c));
+        proptest::prop_assert_eq!(left, right);
+    }
+
+    #[test]
+    fn test_combine_commutative(a in arb_dependencies(), b in arb_dependencies()) {
+        let left = EndpointDependency::combine(a.clone(), b.clone());
+        let right = EndpointDependency::combine(b, a);
+        proptest::prop_assert_eq!(left, right);
+    }
+
+    #[test]
+    fn test_combine_idempotent(a in arb_dependencies()) {
+        // edge metrics are additive (see test_combine_adds_metrics), idempotency holds for the graph
+        let strip = |deps: Vec<EndpointDependency>| -> Vec<EndpointDependency> {
+            deps.into_iter()
+                .map(|mut d| {
+                    d.depending_on.iter_mut().for_each(|i| i.metrics = None);
+                    d.depending_by.iter_mut().for_each(|i| i.metrics = None);
+                    d
+                })
+                .collect()
+        };
+        let once = EndpointDependency::combine(a.clone(), vec![]);
+        let twice = EndpointDependency::combine(once.clone(), a);
+        proptest::prop_assert_eq!(strip(once.clone()), strip(twice));
+        proptest::prop_assert_eq!(once.clone(), EndpointDependency::combine(once, vec![]));
+    }
+
+    #[test]
+    fn test_combine_adds_metrics(a in arb_dependencies()) {
+        let once = EndpointDependency::combine(a.clone(), vec![]);
+        let twice = EndpointDependency::combine(once.clone(), a);
+        for (once, twice) in once.iter().zip(twice.iter()) {
+            let items = once.depending_on.iter().chain(once.depending_by.iter());
+            let doubled = twice.depending_on.iter().chain(twice.depending_by.iter());
+            for (item, doubled) in items.zip(doubled) {
+                let (Some(m), Some(d)) = (&item.metrics, &doubled.metrics) else {
+                    proptest::prop_assert_eq!(&item.metrics, &doubled.metrics);
+                    continue;
+                };
+                proptest::prop_assert_eq!(d.calls, m.calls * 2);
+                proptest::prop_assert_eq!(d.latency_total, m.latency_total * 2);
+                proptest::prop_assert_eq!(d.server_errors, m.server_errors * 2);
+                proptest::prop_assert_eq!(d.latency_mean, m.latency_mean);
+                proptest::prop_assert_eq!(
+                    &d.latency_histogram,
+                    &m.latency_histogram.iter().map(|c| c * 2).collect::<Vec<_>>()
+                );
+                proptest::prop_assert_eq!(
+                    (d.latency_p50, d.first_seen, d.last_seen),
+                    (m.latency_p50, m.first_seen, m.last_seen)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_combine_keeps_minimum_distance() {
+    use crate::data::fixtures::{server, URL_A, URL_B, URL_C};
+    let matcher = crate::http_client::url_matcher::UrlMatcher::new();
+    // A -> C -> B first, then A calls B directly
+    let indirect = Trace::to_endpoint_dependencies(
+        &[vec![
+            server("1", None, URL_A, 0, 100),
+            server("2", Some("1"), URL_C, 10, 80),
+            server("3", Some("2"), URL_B, 20, 50),
+        ]],
+        &matcher,
+    );
+    let direct = Trace::to_endpoint_dependencies(
+        &[vec![
+            server("4", None, URL_A, 0, 100),
+            server("5", Some("4"), URL_B, 10, 50),
+        ]],
+        &matcher,
+    );
+    let combined = EndpointDependency::combine(indirect, direct);
+    let names = combined
+        .iter()
+        .map(|d| d.endpoint.service.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["svc-a", "svc-b", "svc-c"]);
+
+    let a = &combined[0];
+    let on = a
+        .depending_on
+        .iter()
+        .map(|d| (d.endpoint.service.as_str(), d.distance))
+        .collect::<Vec<_>>();
+    assert_eq!(on, vec![("svc-b", 1), ("svc-c", 1)]);
+    assert!(a.depending_by.is_empty());
+}
+
+#[test]
+fn test_expire() {
+    use crate::data::fixtures::{server, URL_A, URL_B, URL_C};
+    let matcher = crate::http_client::url_matcher::UrlMatcher::new();
+    let old = Trace::to_endpoint_dependencies(
+        &[vec![
+            server("1", None, URL_A, 1_000, 100),
+            server("2", Some("1"), URL_C, 1_010, 80),
+        ]],
+        &matcher,
+    );
+    let recent = Trace::to_endpoint_dependencies(
+        &[vec![
+            server("3", None, URL_A, 9_000, 100),
+            server("4", Some("3"), URL_B, 9_010, 50),
+        ]],
+        &matcher,
+    );
+    let combined = EndpointDependency::combine(old, recent);
+    assert_eq!(combined.len(), 3);
+
+    let expired = EndpointDependency::expire(combined, 10_000, 5_000);
+    let names = expired
+        .iter()
+        .map(|d| d.endpoint.service.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["svc-a", "svc-b"]);
+    assert_eq!(expired[0].depending_on.len(), 1);
+    assert_eq!(expired[0].depending_on[0].endpoint.service, "svc-b");
+    assert_eq!(expired[0].depending_on[0].last_seen, Some(9_010));
+
+    let mut legacy = expired[1].clone();
+    legacy.last_seen = None;
+    legacy.depending_by[0].last_seen = None;
+    let stamped = EndpointDependency::expire(vec![legacy], 20_000, 5_000);
+    assert_eq!(stamped[0].last_seen, Some(20_000));
+    assert_eq!(stamped[0].depending_by[0].last_seen, Some(20_000));
 }
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use crate::http_client::url_matcher::UrlMatcher;
+
+use super::{
+    endpoint_dependency::{
+        EndpointDependency, EndpointDependencyItem, EndpointDependencyMetrics,
+        EndpointDependencyType,
+    },
+    endpoint_info::EndpointInfo,
+    trace::{LocalEndpoint, Tags, Trace},
+};
+
+pub const URL_A: &str = "http://svc-a.ns.svc.cluster.local/a";
+pub const URL_A2: &str = "http://svc-a.ns.svc.cluster.local/a2";
+pub const URL_B: &str = "http://svc-b.ns.svc.cluster.local/b";
+pub const URL_B2: &str = "http://svc-b.ns.svc.cluster.local/b2";
+pub const URL_C: &str = "http://svc-c.ns.svc.cluster.local/c";
+
+/// A span of trace "trace" in namespace "ns", the service is the first label of the URL host.
+pub fn span(
+    kind: &str,
+    id: &str,
+    parent_id: Option<&str>,
+    url: &str,
+    timestamp: u64,
+    duration: u64,
+) -> Trace {
+    let host = url.split("://").last().unwrap_or(url);
+    let host = host.split(['/', ':']).next().unwrap_or_default();
+    let service = host.split('.').next().unwrap_or_default();
+    Trace {
+        trace_id: "trace".to_owned(),
+        parent_id: parent_id.map(|p| p.to_owned()),
+        id: id.to_owned(),
+        kind: kind.to_owned(),
+        name: format!("{host}:80/*"),
+        timestamp,
+        duration,
+        local_endpoint: LocalEndpoint {
+            service_name: service.to_owned(),
+            ipv4: "127.0.0.1".to_owned(),
+        },
+        annotations: vec![],
+        processed: false,
+        tags: Tags {
+            component: "proxy".to_owned(),
+            request_id: id.to_owned(),
+            http_method: "GET".to_owned(),
+            http_protocol: "HTTP/1.1".to_owned(),
+            http_status_code: "200".to_owned(),
+            http_url: url.to_owned(),
+            istio_canonical_revision: "latest".to_owned(),
+            istio_canonical_service: service.to_owned(),
+            istio_mesh_id: "cluster.local".to_owned(),
+            istio_namespace: "ns".to_owned(),
+            ..Default::default()
+        },
+    }
+}
+
+pub fn server(
+    id: &str,
+    parent_id: Option<&str>,
+    url: &str,
+    timestamp: u64,
+    duration: u64,
+) -> Trace {
+    span("SERVER", id, parent_id, url, timestamp, duration)
+}
+
+pub fn client(
+    id: &str,
+    parent_id: Option<&str>,
+    url: &str,
+    timestamp: u64,
+    duration: u64,
+) -> Trace {
+    span("CLIENT", id, parent_id, url, timestamp, duration)
+}
+
+pub fn url(service: &str, path: &str) -> String {
+    format!("http://{service}.ns.svc.cluster.local/{path}")
+}
+
+/// The endpoint a GET to the URL is recorded as.
+pub fn endpoint(url: &str) -> EndpointInfo {
+    server("1", None, url, 0, 10)
+        .to_endpoint_info(&UrlMatcher::new())
+        .unwrap()
+}
+
+/// Dependencies of the (caller, callee, distance) links, direct links carry a single call.
+pub fn dependencies(links: &[(&EndpointInfo, &EndpointInfo, u32)]) -> Vec<EndpointDependency> {
+    fn entry<'a>(
+        dependencies: &'a mut BTreeMap<String, EndpointDependency>,
+        endpoint: &EndpointInfo,
+    ) -> &'a mut EndpointDependency {
+        dependencies
+            .entry(endpoint.unique_endpoint_name.clone())
+            .or_insert_with(|| EndpointDependency {
+                _id: None,
+                endpoint: endpoint.clone(),
+                depending_on: vec![],
+                depending_by: vec![],
+                last_seen: None,
+            })
+    }
+
+    let mut dependencies = BTreeMap::new();
+    for &(caller, callee, distance) in links.iter() {
+        let item = |endpoint: &EndpointInfo, r#type| EndpointDependencyItem {
+            endpoint: endpoint.clone(),
+            distance,
+            r#type,
+            metrics: (distance == 1).then(|| {
+                EndpointDependencyMetrics::from_span(&client("1", None, &callee.url, 0, 10))
+            }),
+            last_seen: None,
+        };
+        let on = item(callee, EndpointDependencyType::Server);
+        entry(&mut dependencies, caller).depending_on.push(on);
+        let by = item(caller, EndpointDependencyType::Client);
+        entry(&mut dependencies, callee).depending_by.push(by);
+    }
+    dependencies.into_values().collect()
+}
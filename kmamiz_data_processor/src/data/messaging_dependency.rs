@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{span_tree::SpanTree, trace::Trace};
+
+/// A topic or queue, with the services producing to and consuming from it.
+/// Edges go producer service -> topic -> consumer service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagingDependency {
+    // "{system}\t{destination}"
+    pub unique_topic_name: String,
+    // kafka, rabbitmq, nats, ...
+    pub system: String,
+    pub destination: String,
+    pub producers: Vec<MessagingLink>,
+    pub consumers: Vec<MessagingLink>,
+    pub metrics: MessagingMetrics,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagingLink {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    pub messages: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MessagingMetrics {
+    pub produced: u64,
+    pub consumed: u64,
+    // messages per second over the processed window
+    pub produce_rate: f64,
+    pub consume_rate: f64,
+    // consumer lag in microseconds, from CONSUMER spans whose parent is the PRODUCER span
+    pub lag_samples: u64,
+    pub lag_mean: f64,
+    pub lag_max: u64,
+}
+
+impl MessagingDependency {
+    /// `window` is the length of the processed window in milliseconds, used for the rates.
+    pub fn from_traces(traces: &[Vec<Trace>], window: u64) -> Vec<MessagingDependency> {
+        let mut topics: BTreeMap<(String, String), TopicAccumulator> = BTreeMap::new();
+        let sidecars = Self::sidecar_services(traces);
+
+        for tree in SpanTree::from_traces(traces).iter() {
+            for (i, node) in tree.nodes.iter().enumerate() {
+                let span = node.span;
+                if span.processed {
+                    continue;
+                }
+                let Some(destination) = span.to_messaging_destination() else {
+                    continue;
+                };
+                let topic = topics.entry(destination).or_default();
+                let is_producer = span.kind == *"PRODUCER";
+                let links = if is_producer {
+                    &mut topic.producers
+                } else {
+                    &mut topic.consumers
+                };
+                let (service, namespace, version) = Self::service_info_of(tree, i, &sidecars);
+                let unique_service_name = format!("{service}\t{namespace}\t{version}");
+                let link =
+                    links
+                        .entry(unique_service_name.clone())
+                        .or_insert_with(|| MessagingLink {
+                            unique_service_name,
+                            service,
+                            namespace,
+                            version,
+                            messages: 0,
+                            last_seen: 0,
+                        });
+                link.messages += 1;
+                link.last_seen = link.last_seen.max(node.timestamp);
+
+                if is_producer {
+                    topic.metrics.produced += 1;
+                    continue;
+                }
+                topic.metrics.consumed += 1;
+                let producer = node
+                    .parent
+                    .map(|p| &tree.nodes[p])
+                    .filter(|p| p.span.kind == *"PRODUCER");
+                if let Some(producer) = producer {
+                    let lag = node.timestamp.saturating_sub(producer.timestamp);
+                    topic.lag_total += lag;
+                    topic.metrics.lag_samples += 1;
+                    topic.metrics.lag_max = topic.metrics.lag_max.max(lag);
+                }
+            }
+        }
+
+        let seconds = window as f64 / 1000.0;
+        topics
+            .into_iter()
+            .map(|((system, destination), topic)| {
+                let mut metrics = topic.metrics;
+                if seconds > 0.0 {
+                    metrics.produce_rate = metrics.produced as f64 / seconds;
+                    metrics.consume_rate = metrics.consumed as f64 / seconds;
+                }
+                if metrics.lag_samples > 0 {
+                    metrics.lag_mean = topic.lag_total as f64 / metrics.lag_samples as f64;
+                }
+                MessagingDependency {
+                    unique_topic_name: format!("{system}\t{destination}"),
+                    system,
+                    destination,
+                    producers: topic.producers.into_values().collect(),
+                    consumers: topic.consumers.into_values().collect(),
+                    metrics,
+                }
+            })
+            .collect()
+    }
+
+    /// Service names recorded by istio to their (service, namespace, version), if unambiguous.
+    fn sidecar_services(traces: &[Vec<Trace>]) -> HashMap<String, (String, String, String)> {
+        let mut services: HashMap<String, HashSet<(String, String, String)>> = HashMap::new();
+        for span in traces.iter().flatten() {
+            if !span.tags.istio_namespace.is_empty() {
+                services
+                    .entry(span.tags.istio_canonical_service.clone())
+                    .or_default()
+                    .insert(span.to_service_info());
+            }
+        }
+        services
+            .into_iter()
+            .filter(|(_, infos)| infos.len() == 1)
+            .filter_map(|(service, infos)| Some((service, infos.into_iter().next()?)))
+            .collect()
+    }
+
+    /// Messaging libraries do not know the namespace and version of their service, they are
+    /// taken from the nearest span recorded by istio in the same process, or another span of
+    /// the service. Falls back to the messaging span itself.
+    fn service_info_of(
+        tree: &SpanTree,
+        i: usize,
+        sidecars: &HashMap<String, (String, String, String)>,
+    ) -> (String, String, String) {
+        let span = tree.nodes[i].span;
+        let ancestor = tree
+            .ancestors(i)
+            .map(|p| tree.nodes[p].span)
+            // the parent of a CONSUMER span is in the producing service
+            .take_while(|s| s.kind != *"PRODUCER" && s.kind != *"CONSUMER")
+            .find(|s| !s.tags.istio_namespace.is_empty());
+        if let Some(ancestor) = ancestor {
+            return ancestor.to_service_info();
+        }
+        let (service, namespace, version) = span.to_service_info();
+        match sidecars.get(&service) {
+            Some(info) if namespace.is_empty() => info.clone(),
+            _ => (service, namespace, version),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TopicAccumulator {
+    producers: BTreeMap<String, MessagingLink>,
+    consumers: BTreeMap<String, MessagingLink>,
+    metrics: MessagingMetrics,
+    lag_total: u64,
+}
+
+#[test]
+fn test_messaging_dependencies() {
+    use crate::data::fixtures::{client, server, span, URL_A, URL_C};
+    use crate::http_client::url_matcher::UrlMatcher;
+
+    let messaging = |kind: &str, id: &str, parent_id: Option<&str>, service: &str, ts: u64| {
+        let mut span = span(kind, id, parent_id, "", ts, 10);
+        span.tags = Default::default();
+        span.tags.kafka_topic = Some("orders".to_owned());
+        span.local_endpoint.service_name = service.to_owned();
+        span
+    };
+    // the sidecar of B records the outgoing call
+    let mut call = client("4", Some("3"), URL_C, 1_020, 50);
+    call.tags.istio_canonical_service = "svc-b".to_owned();
+    // A -(orders)-> B -> C, A -(orders)-> B
+    let traces = vec![
+        vec![
+            server("1", None, URL_A, 0, 100),
+            messaging("PRODUCER", "2", Some("1"), "svc-a", 10),
+            messaging("CONSUMER", "3", Some("2"), "svc-b", 1_010),
+            call,
+            server("5", Some("4"), URL_C, 1_030, 30),
+        ],
+        vec![
+            server("6", None, URL_A, 0, 100),
+            messaging("PRODUCER", "7", Some("6"), "svc-a", 10),
+            messaging("CONSUMER", "8", Some("7"), "svc-b", 3_010),
+        ],
+    ];
+
+    let topics = MessagingDependency::from_traces(&traces, 2000);
+    assert_eq!(topics.len(), 1);
+    let orders = &topics[0];
+    assert_eq!(orders.unique_topic_name, "kafka\torders");
+    assert_eq!(orders.producers.len(), 1);
+    // joined to the services of the endpoint graph
+    assert_eq!(orders.producers[0].unique_service_name, "svc-a\tns\tlatest");
+    assert_eq!(orders.producers[0].messages, 2);
+    assert_eq!(orders.consumers.len(), 1);
+    assert_eq!(orders.consumers[0].unique_service_name, "svc-b\tns\tlatest");
+    assert_eq!(orders.consumers[0].last_seen, 3_010);
+    assert_eq!(orders.metrics.produced, 2);
+    assert_eq!(orders.metrics.consume_rate, 1.0);
+    assert_eq!(orders.metrics.lag_samples, 2);
+    assert_eq!(orders.metrics.lag_mean, 2000.0);
+    assert_eq!(orders.metrics.lag_max, 3000);
+
+    // the asynchronous hop does not make C a synchronous dependency of A
+    let dependencies = Trace::to_endpoint_dependencies(&traces, &UrlMatcher::new());
+    let c = dependencies
+        .iter()
+        .find(|d| d.endpoint.service == "svc-c")
+        .unwrap();
+    assert!(c.depending_by.is_empty());
+}
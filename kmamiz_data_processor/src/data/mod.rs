@@ -1,11 +1,26 @@
+pub mod body_parser;
+pub mod body_sampler;
+pub mod body_statistics;
 pub mod combined_realtime_data;
 pub mod connection_package;
+pub mod coupling;
+pub mod critical_path;
+pub mod datastore_dependency;
+pub mod dependency_cycle;
 pub mod endpoint_data_type;
 pub mod endpoint_dependency;
 pub mod endpoint_info;
 pub mod envoy_log;
+#[cfg(test)]
+pub mod fixtures;
+pub mod messaging_dependency;
 pub mod pod_list;
 pub mod realtime_data;
 pub mod replica_count;
 pub mod request_type;
+pub mod risk;
+pub mod sampling;
+pub mod schema_change;
+pub mod service_dependency;
+pub mod span_tree;
 pub mod trace;
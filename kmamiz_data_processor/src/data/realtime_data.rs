@@ -1,13 +1,14 @@
 use std::collections::HashMap;
 
-use crate::json_utils;
+use crate::{json_schema, json_utils};
 
 use super::{
+    body_sampler::BodySampler,
     combined_realtime_data::{CombinedLatency, CombinedRealtimeData},
     request_type::RequestType,
+    sampling::SamplingConfig,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -26,10 +27,15 @@ pub struct RealtimeData {
     pub response_body: Option<String>,
     pub response_content_type: Option<String>,
     pub replica: Option<u32>,
+    // percent of requests traced
+    pub sampling_rate: f64,
 }
 
 impl RealtimeData {
-    pub fn combine(data: Vec<RealtimeData>) -> Vec<CombinedRealtimeData> {
+    /// `window` is the length of the processed window in milliseconds, used for the request rates.
+    pub fn combine(data: Vec<RealtimeData>, window: u64) -> Vec<CombinedRealtimeData> {
+        let seconds = window as f64 / 1000.0;
+        let per_second = |count: f64| if seconds > 0.0 { count / seconds } else { 0.0 };
         let mut name_mapping = HashMap::new();
         data.into_iter().for_each(|d| {
             let id = format!(
@@ -52,16 +58,19 @@ impl RealtimeData {
                 let mut total_latency = 0;
                 let mut total_replicas = 0;
                 let mut latest_timestamp = 0;
-                let mut request_body = vec![];
-                let mut response_body = vec![];
+                let mut request_body = BodySampler::new(sample.request_content_type.clone());
+                let mut response_body = BodySampler::new(sample.response_content_type.clone());
                 let mut div_base = 0;
+                // each sampled request stands for 100 / rate requests
+                let mut estimated = 0.0;
                 for data in group.into_iter() {
                     total_latency += data.latency;
+                    estimated += 100.0 / SamplingConfig::clamp(data.sampling_rate);
                     if let Some(body) = data.request_body {
-                        request_body.push(body);
+                        request_body.add(&body);
                     }
                     if let Some(body) = data.response_body {
-                        response_body.push(body);
+                        response_body.add(&body);
                     }
                     latest_timestamp = latest_timestamp.max(data.timestamp);
                     div_base += data.latency.pow(2);
@@ -80,8 +89,8 @@ impl RealtimeData {
                 };
                 let latency = CombinedLatency { mean, div_base, cv };
 
-                let request_body = Self::process_body(request_body);
-                let response_body = Self::process_body(response_body);
+                let (request_body, request_schema, request_statistics) = request_body.finish();
+                let (response_body, response_schema, response_statistics) = response_body.finish();
 
                 CombinedRealtimeData {
                     unique_service_name: sample.unique_service_name,
@@ -94,12 +103,20 @@ impl RealtimeData {
                     request_content_type: sample.request_content_type,
                     response_content_type: sample.response_content_type,
                     combined: combined as usize,
+                    estimated_combined: estimated,
+                    sampling_rate: combined / estimated * 100.0,
+                    request_rate: per_second(combined),
+                    estimated_request_rate: per_second(estimated),
                     latency,
                     latest_timestamp,
-                    request_body: serde_json::to_string(&request_body).ok(),
-                    response_body: serde_json::to_string(&response_body).ok(),
-                    request_schema: Some(json_utils::to_types(request_body)),
-                    response_schema: Some(json_utils::to_types(response_body)),
+                    request_body: Some(request_body.to_string()),
+                    response_body: Some(response_body.to_string()),
+                    request_schema: Some(json_utils::to_types(&request_schema)),
+                    response_schema: Some(json_utils::to_types(&response_schema)),
+                    request_json_schema: Some(json_schema::to_json_schema(&request_schema)),
+                    response_json_schema: Some(json_schema::to_json_schema(&response_schema)),
+                    request_statistics: Some(request_statistics),
+                    response_statistics: Some(response_statistics),
                     avg_replica: total_replicas as f64 / combined,
                     _id: None,
                 }
@@ -107,15 +124,38 @@ impl RealtimeData {
             .collect()
     }
 
-    fn process_body(samples: Vec<String>) -> Value {
-        let samples = samples
-            .into_iter()
-            .filter_map(|req| serde_json::from_str(&req).ok())
-            .collect::<Vec<Value>>();
-        json_utils::merge(samples)
-    }
-
     fn to_precise(num: f64) -> f64 {
         ((num + f64::EPSILON) * 1e14).round() / 1e14
     }
 }
+
+#[test]
+fn test_combine_extrapolates_sampled_requests() {
+    let data = [10.0, 10.0, 50.0]
+        .into_iter()
+        .map(|sampling_rate| RealtimeData {
+            unique_service_name: "svc-a\tns\tlatest".to_owned(),
+            unique_endpoint_name: "svc-a\tns\tlatest\tGET\thttp://svc-a/a".to_owned(),
+            timestamp: 0,
+            method: RequestType::Get,
+            service: "svc-a".to_owned(),
+            namespace: "ns".to_owned(),
+            version: "latest".to_owned(),
+            latency: 10,
+            status: "200".to_owned(),
+            request_body: None,
+            request_content_type: None,
+            response_body: None,
+            response_content_type: None,
+            replica: None,
+            sampling_rate,
+        })
+        .collect();
+
+    let combined = RealtimeData::combine(data, 2000);
+    assert_eq!(combined.len(), 1);
+    assert_eq!(combined[0].combined, 3);
+    assert_eq!(combined[0].estimated_combined, 22.0);
+    assert_eq!(combined[0].request_rate, 1.5);
+    assert_eq!(combined[0].estimated_request_rate, 11.0);
+}
@@ -0,0 +1,389 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    combined_realtime_data::CombinedRealtimeData, endpoint_dependency::EndpointDependency,
+    replica_count::ReplicaCount, service_dependency::ServiceDependency,
+};
+
+// port of src/utils/RiskAnalyzer.ts, keep the two in sync
+const MINIMUM_PROB: f64 = 0.01;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskResult {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    pub risk: f64,
+    pub impact: f64,
+    pub probability: f64,
+    pub norm: f64,
+}
+
+pub fn realtime_risk(
+    data: &[CombinedRealtimeData],
+    dependencies: &[EndpointDependency],
+    replicas: &[ReplicaCount],
+) -> Vec<RiskResult> {
+    let impacts = impact(dependencies, replicas);
+    let probabilities = probability(data);
+
+    let mut services = data
+        .iter()
+        .map(|d| d.unique_service_name.as_str())
+        .collect::<Vec<_>>();
+    services.sort();
+    services.dedup();
+
+    let risks = services
+        .into_iter()
+        .map(|s| {
+            let mut tokens = s.split('\t');
+            let impact = impacts.get(s).copied().unwrap_or(0.0);
+            let probability = probabilities
+                .get(s)
+                .copied()
+                .filter(|p| *p != 0.0)
+                .unwrap_or(MINIMUM_PROB);
+            RiskResult {
+                unique_service_name: s.to_owned(),
+                service: tokens.next().unwrap_or_default().to_owned(),
+                namespace: tokens.next().unwrap_or_default().to_owned(),
+                version: tokens.next().unwrap_or_default().to_owned(),
+                risk: impact * probability,
+                impact,
+                probability,
+                norm: 0.0,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let norm = between_fixed_number(&risks.iter().map(|r| r.risk).collect::<Vec<_>>());
+    risks
+        .into_iter()
+        .zip(norm)
+        .map(|(r, norm)| RiskResult { norm, ..r })
+        .collect()
+}
+
+/// raw impact = (normalized relying factor + normalized ACS) / replicas
+fn impact(dependencies: &[EndpointDependency], replicas: &[ReplicaCount]) -> HashMap<String, f64> {
+    let service_dependencies = ServiceDependency::from_endpoint_dependencies(dependencies);
+    let mut gateways = HashMap::new();
+    for dep in dependencies.iter() {
+        let entry = gateways
+            .entry(dep.endpoint.unique_service_name.as_str())
+            .or_insert(false);
+        *entry |= dep.depending_by.is_empty();
+    }
+    let is_gateway = |s: &String| gateways.get(s.as_str()).copied().unwrap_or(false);
+
+    // sorted by unique service name, links to the service itself count as in RiskAnalyzer
+    let relying_factor = fixed_ratio(
+        &service_dependencies
+            .iter()
+            .map(|s| {
+                let factor: f64 = s
+                    .links
+                    .iter()
+                    .flat_map(|l| l.details.iter())
+                    .map(|d| d.depending_by as f64 / d.distance as f64)
+                    .sum();
+                factor
+                    + if is_gateway(&s.unique_service_name) {
+                        1.0
+                    } else {
+                        0.0
+                    }
+            })
+            .collect::<Vec<_>>(),
+    );
+    let acs = fixed_ratio(
+        &service_dependencies
+            .iter()
+            .map(|s| {
+                let (mut ais, mut ads) = (is_gateway(&s.unique_service_name) as usize, 0);
+                s.links
+                    .iter()
+                    .flat_map(|l| l.details.iter())
+                    .filter(|d| d.distance == 1)
+                    .for_each(|d| {
+                        ais += (d.depending_by > 0) as usize;
+                        ads += (d.depending_on > 0) as usize;
+                    });
+                (ais * ads) as f64
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let replica_map = replicas
+        .iter()
+        .map(|r| (r.unique_service_name.as_str(), r.replicas))
+        .collect::<HashMap<_, _>>();
+    let raw_impact = service_dependencies
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let replicas = replica_map
+                .get(s.unique_service_name.as_str())
+                .copied()
+                .filter(|r| *r != 0)
+                .unwrap_or(1);
+            (relying_factor[i] + acs[i]) / replicas as f64
+        })
+        .collect::<Vec<_>>();
+
+    service_dependencies
+        .into_iter()
+        .zip(linear(&raw_impact, 0.1))
+        .map(|(s, impact)| (s.unique_service_name, impact))
+        .collect()
+}
+
+fn probability(data: &[CombinedRealtimeData]) -> HashMap<String, f64> {
+    // (combined, errors, cv * combined)
+    let mut services: BTreeMap<&str, (usize, usize, f64)> = BTreeMap::new();
+    for d in data.iter() {
+        let entry = services
+            .entry(d.unique_service_name.as_str())
+            .or_insert((0, 0, 0.0));
+        entry.0 += d.combined;
+        if d.status.starts_with('5') {
+            entry.1 += d.combined;
+        }
+        entry.2 += d.latency.cv * d.combined as f64;
+    }
+
+    let total = services.values().map(|(count, _, _)| count).sum::<usize>() as f64;
+    let base = services
+        .values()
+        .map(|(count, error, _)| {
+            let probability = *count as f64 / total;
+            let error_rate = *error as f64 / *count as f64;
+            (probability * (1.0 - MINIMUM_PROB) + MINIMUM_PROB)
+                * (error_rate * (1.0 - MINIMUM_PROB) + MINIMUM_PROB)
+        })
+        .collect::<Vec<_>>();
+    let base = linear(&base, MINIMUM_PROB);
+    let reliability = sigmoid_adj(
+        &services
+            .values()
+            .map(|(count, _, cv)| cv / *count as f64)
+            .collect::<Vec<_>>(),
+    );
+
+    services
+        .into_keys()
+        .zip(base.into_iter().zip(reliability))
+        .map(|(s, (prob, norm))| {
+            let probability = norm * prob.max(MINIMUM_PROB);
+            (
+                s.to_owned(),
+                probability * (1.0 - MINIMUM_PROB) + MINIMUM_PROB,
+            )
+        })
+        .collect()
+}
+
+fn to_precise(num: f64) -> f64 {
+    ((num + f64::EPSILON) * 1e14).round() / 1e14
+}
+
+/// Scales into [0.1, 1], all values are 0.1 if they are the same.
+/// Normalizer.BetweenFixedNumber returns a single [0.1] then, leaving `norm` undefined for
+/// every other service, here each of them gets the 0.1 instead.
+fn between_fixed_number(input: &[f64]) -> Vec<f64> {
+    let base_line = 0.1;
+    let max = input.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let min = input.iter().copied().fold(f64::INFINITY, f64::min);
+    if max - min == 0.0 {
+        return vec![base_line; input.len()];
+    }
+    input
+        .iter()
+        .map(|v| (v - min) / (max - min) * (1.0 - base_line) + base_line)
+        .collect()
+}
+
+/// Scales [0, Inf] into [0, 1] with y = 1 / (1 + e^(-z * (x - 1.5))), z = 2 * ln(3)
+fn sigmoid_adj(input: &[f64]) -> Vec<f64> {
+    let z = 2.0 * 3f64.ln();
+    input
+        .iter()
+        .map(|v| to_precise(1.0 / (1.0 + (-z * (v - 1.5)).exp())))
+        .collect()
+}
+
+fn fixed_ratio(input: &[f64]) -> Vec<f64> {
+    let max = input.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == 0.0 {
+        return input.to_vec();
+    }
+    input.iter().map(|v| v / max).collect()
+}
+
+fn linear(input: &[f64], minimum: f64) -> Vec<f64> {
+    if minimum >= 1.0 {
+        return input.to_vec();
+    }
+    fixed_ratio(input)
+        .into_iter()
+        .map(|n| n * (1.0 - minimum) + minimum)
+        .collect()
+}
+
+#[cfg(test)]
+fn risk_fixture() -> (
+    Vec<CombinedRealtimeData>,
+    Vec<EndpointDependency>,
+    Vec<ReplicaCount>,
+) {
+    use crate::data::fixtures::{dependencies, endpoint, url};
+    use crate::data::{combined_realtime_data::CombinedLatency, request_type::RequestType};
+
+    let [gw, a, a2, b, c, d] = [
+        ("gw", "in"),
+        ("a", "a"),
+        ("a", "a2"),
+        ("b", "b"),
+        ("c", "c"),
+        ("d", "d"),
+    ]
+    .map(|(service, path)| endpoint(&url(service, path)));
+    // gw -> a -> b -> c, gw -> a2 -> c, a2 -> d, with the shortest distance of every pair
+    let dependencies = dependencies(&[
+        (&gw, &a, 1),
+        (&gw, &b, 2),
+        (&gw, &c, 2),
+        (&gw, &a2, 1),
+        (&gw, &d, 2),
+        (&a, &b, 1),
+        (&a, &c, 2),
+        (&b, &c, 1),
+        (&a2, &c, 1),
+        (&a2, &d, 1),
+    ]);
+
+    let combined = [
+        ("gw", "in", "200", 40, 0.2),
+        ("a", "a", "200", 20, 0.5),
+        ("a", "a2", "500", 5, 1.4),
+        ("a", "a2", "200", 15, 0.3),
+        ("b", "b", "200", 20, 2.1),
+        ("c", "c", "503", 10, 0.9),
+        ("c", "c", "200", 25, 0.1),
+        ("d", "d", "200", 15, 0.0),
+    ]
+    .into_iter()
+    .map(|(service, path, status, combined, cv)| {
+        let unique_service_name = format!("{service}\tns\tlatest");
+        CombinedRealtimeData {
+            _id: None,
+            unique_endpoint_name: format!("{unique_service_name}\tGET\t{}", url(service, path)),
+            unique_service_name,
+            latest_timestamp: 0,
+            method: RequestType::Get,
+            service: service.to_owned(),
+            namespace: "ns".to_owned(),
+            version: "latest".to_owned(),
+            latency: CombinedLatency {
+                mean: 10.0,
+                div_base: 0.0,
+                cv,
+            },
+            combined,
+            estimated_combined: combined as f64,
+            sampling_rate: 100.0,
+            request_rate: 0.0,
+            estimated_request_rate: 0.0,
+            status: status.to_owned(),
+            request_body: None,
+            request_schema: None,
+            request_json_schema: None,
+            request_content_type: None,
+            response_body: None,
+            response_schema: None,
+            response_json_schema: None,
+            request_statistics: None,
+            response_statistics: None,
+            response_content_type: None,
+            avg_replica: 1.0,
+        }
+    })
+    .collect();
+
+    let replicas = [("a", 2), ("c", 3)]
+        .into_iter()
+        .map(|(service, replicas)| ReplicaCount {
+            unique_service_name: format!("{service}\tns\tlatest"),
+            service: service.to_owned(),
+            namespace: "ns".to_owned(),
+            version: "latest".to_owned(),
+            replicas,
+        })
+        .collect();
+
+    (combined, dependencies, replicas)
+}
+
+#[test]
+fn test_realtime_risk_parity() {
+    let (combined, dependencies, replicas) = risk_fixture();
+    let risks = realtime_risk(&combined, &dependencies, &replicas);
+
+    // RiskAnalyzer.RealtimeRisk output for the same fixture, (service, risk, impact, probability, norm)
+    let expected = [
+        (
+            "a",
+            0.054002915406268164,
+            0.8200000000000001,
+            0.06585721391008312,
+            1.0,
+        ),
+        (
+            "b",
+            0.03336659813721339,
+            1.0,
+            0.03336659813721339,
+            0.6050940379959594,
+        ),
+        (
+            "c",
+            0.03685950161720031,
+            0.4600000000000001,
+            0.08012935134173979,
+            0.6719358291434491,
+        ),
+        (
+            "d",
+            0.0069722617086183415,
+            0.6400000000000001,
+            0.010894158919716157,
+            0.1,
+        ),
+        (
+            "gw",
+            0.0103370346509694,
+            0.8200000000000001,
+            0.012606139818255365,
+            0.16438982684749032,
+        ),
+    ];
+    assert_eq!(risks.len(), expected.len());
+    for (risk, (service, r, impact, probability, norm)) in risks.iter().zip(expected) {
+        assert_eq!(risk.service, service);
+        assert!((risk.risk - r).abs() < 1e-12, "{service} risk");
+        assert!((risk.impact - impact).abs() < 1e-12, "{service} impact");
+        assert!(
+            (risk.probability - probability).abs() < 1e-12,
+            "{service} probability"
+        );
+        assert!((risk.norm - norm).abs() < 1e-12, "{service} norm");
+    }
+
+    // the only deviation, equal risks all get the baseline instead of just the first one
+    assert_eq!(between_fixed_number(&[0.5, 0.5, 0.5]), vec![0.1; 3]);
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::trace::Trace;
+
+/// Sampling rates in percent, same as istio's `tracing.sampling`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SamplingConfig {
+    // used when nothing more specific matches, falls back to the SAMPLING_RATE environment variable
+    pub default_rate: Option<f64>,
+    pub namespaces: HashMap<String, f64>,
+    // keyed by uniqueServiceName, or "{service}\t{namespace}" for every version
+    pub services: HashMap<String, f64>,
+}
+
+impl SamplingConfig {
+    /// Rate the span was sampled at, span tags take precedence over the configured rates.
+    pub fn rate_of(&self, span: &Trace) -> f64 {
+        let tags = &span.tags;
+        let service = &tags.istio_canonical_service;
+        let namespace = &tags.istio_namespace;
+        let version = &tags.istio_canonical_revision;
+        let rate = Self::rate_from_tags(span)
+            .or_else(|| {
+                self.services
+                    .get(&format!("{service}\t{namespace}\t{version}"))
+                    .copied()
+            })
+            .or_else(|| {
+                self.services
+                    .get(&format!("{service}\t{namespace}"))
+                    .copied()
+            })
+            .or_else(|| self.namespaces.get(namespace).copied())
+            .or(self.default_rate)
+            .unwrap_or(100.0);
+        Self::clamp(rate)
+    }
+
+    /// Jaeger style `sampler.type` and `sampler.param` tags, the param is a probability.
+    fn rate_from_tags(span: &Trace) -> Option<f64> {
+        let tags = &span.tags;
+        if tags.sampler_type.as_deref() != Some("probabilistic") {
+            return None;
+        }
+        let probability = tags.sampler_param.as_deref()?.parse::<f64>().ok()?;
+        Some(probability * 100.0)
+    }
+
+    /// Rates outside of (0, 100] are treated as unsampled.
+    pub fn clamp(rate: f64) -> f64 {
+        if rate > 0.0 && rate <= 100.0 {
+            rate
+        } else {
+            100.0
+        }
+    }
+}
+
+#[test]
+fn test_rate_of() {
+    use crate::data::fixtures::{server, URL_A};
+
+    let mut config = SamplingConfig {
+        default_rate: None,
+        namespaces: HashMap::from([("ns".to_owned(), 10.0)]),
+        services: HashMap::from([("svc-a\tns".to_owned(), 50.0)]),
+    };
+    let mut span = server("1", None, URL_A, 0, 10);
+    assert_eq!(config.rate_of(&span), 50.0);
+
+    span.tags.istio_canonical_service = "svc-b".to_owned();
+    assert_eq!(config.rate_of(&span), 10.0);
+    span.tags.istio_namespace = "other".to_owned();
+    assert_eq!(config.rate_of(&span), 100.0);
+    config.default_rate = Some(1.0);
+    assert_eq!(config.rate_of(&span), 1.0);
+
+    span.tags.sampler_type = Some("probabilistic".to_owned());
+    span.tags.sampler_param = Some("0.25".to_owned());
+    assert_eq!(config.rate_of(&span), 25.0);
+    span.tags.sampler_param = Some("0".to_owned());
+    assert_eq!(config.rate_of(&span), 100.0);
+}
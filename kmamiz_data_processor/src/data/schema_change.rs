@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{json_schema, json_utils::Schema, openapi::is_empty_body};
+
+use super::{body_statistics::BodyStatistics, endpoint_data_type::EndpointDataType};
+
+// objects a window has to observe without a required field before it is reported as removed
+const MIN_OBJECTS: u64 = 10;
+
+/// Ordered by severity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    // formats and enum values
+    Cosmetic,
+    // fields or types added, required fields becoming optional
+    Additive,
+    // required fields missing, types changed
+    Breaking,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SchemaBody {
+    Request,
+    Response,
+}
+
+/// Differences between the stored schemas of an endpoint's status and the newly inferred ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointSchemaChange {
+    pub unique_endpoint_name: String,
+    pub status: String,
+    // the most severe of the changes
+    pub kind: ChangeKind,
+    pub changes: Vec<SchemaChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaChange {
+    pub body: SchemaBody,
+    // "user.address.city", "items[]", "" for the body itself
+    pub path: String,
+    pub kind: ChangeKind,
+    pub description: String,
+}
+
+/// JSON Schema node with references resolved and unions flattened.
+#[derive(Debug, Default)]
+struct Shape {
+    types: BTreeSet<String>,
+    // property to its shape and whether it is required
+    properties: BTreeMap<String, (Shape, bool)>,
+    items: Option<Box<Shape>>,
+    format: Option<String>,
+    values: Option<Vec<Value>>,
+}
+
+impl Shape {
+    fn from_json_schema(schema: &Value) -> Shape {
+        let mut shape = Shape::default();
+        shape.add(schema, schema, 0);
+        shape
+    }
+
+    fn add(&mut self, node: &Value, root: &Value, depth: usize) {
+        // references are never cyclic in inferred schemas, the limit guards against stored ones
+        if depth > 64 {
+            return;
+        }
+        if let Some(name) = node["$ref"]
+            .as_str()
+            .and_then(|r| r.strip_prefix("#/$defs/"))
+        {
+            self.add(&root["$defs"][name], root, depth + 1);
+        }
+        for variant in node["anyOf"].as_array().into_iter().flatten() {
+            self.add(variant, root, depth + 1);
+        }
+        match &node["type"] {
+            Value::String(ty) => {
+                self.types.insert(ty.clone());
+            }
+            Value::Array(types) => {
+                let types = types.iter().filter_map(|t| t.as_str()).map(str::to_owned);
+                self.types.extend(types);
+            }
+            _ => {}
+        }
+        if let Some(properties) = node["properties"].as_object() {
+            let required = node["required"].as_array();
+            for (name, property) in properties.iter() {
+                let entry = self.properties.entry(name.clone()).or_default();
+                entry.0.add(property, root, depth + 1);
+                entry.1 = required.is_some_and(|r| r.iter().any(|n| n == name));
+            }
+        }
+        if node.get("items").is_some() {
+            let items = self.items.get_or_insert_with(Default::default);
+            items.add(&node["items"], root, depth + 1);
+        }
+        if let Some(format) = node["format"].as_str().or(node["contentEncoding"].as_str()) {
+            self.format = Some(format.to_owned());
+        }
+        if let Some(values) = node["enum"].as_array() {
+            self.values = Some(values.clone());
+        }
+    }
+
+    /// Nothing observed, e.g. the elements of empty arrays.
+    fn is_unknown(&self) -> bool {
+        self.types.is_empty()
+    }
+}
+
+/// Compares the stored schema, which covers every processed window, with the one of a window.
+/// A window can miss optional fields and rare enum values, only what it contradicts is reported.
+struct Differ<'a> {
+    body: SchemaBody,
+    // statistics of the window
+    statistics: Option<&'a BodyStatistics>,
+    changes: Vec<SchemaChange>,
+}
+
+impl Differ<'_> {
+    fn push(&mut self, path: &str, kind: ChangeKind, description: String) {
+        self.changes.push(SchemaChange {
+            body: self.body,
+            path: path.to_owned(),
+            kind,
+            description,
+        });
+    }
+
+    fn diff(&mut self, path: &str, old: &Shape, new: &Shape) {
+        // a shape without observations says nothing about the type
+        if new.is_unknown() {
+            return;
+        }
+        if old.is_unknown() {
+            let types = new.types.iter().cloned().collect::<Vec<_>>().join(" | ");
+            self.push(path, ChangeKind::Additive, format!("type {types} observed"));
+            return;
+        }
+
+        let removed = old.types.difference(&new.types).collect::<Vec<_>>();
+        let added = new.types.difference(&old.types).collect::<Vec<_>>();
+        if !removed.is_empty() || !added.is_empty() {
+            let join = |types: &BTreeSet<String>| types.iter().cloned().collect::<Vec<_>>();
+            let kind = if removed.is_empty() {
+                ChangeKind::Additive
+            } else {
+                ChangeKind::Breaking
+            };
+            self.push(
+                path,
+                kind,
+                format!(
+                    "type changed from {} to {}",
+                    join(&old.types).join(" | "),
+                    join(&new.types).join(" | ")
+                ),
+            );
+        }
+
+        if old.format != new.format {
+            let describe = |f: &Option<String>| f.clone().unwrap_or_else(|| "none".to_owned());
+            self.push(
+                path,
+                ChangeKind::Cosmetic,
+                format!(
+                    "format changed from {} to {}",
+                    describe(&old.format),
+                    describe(&new.format)
+                ),
+            );
+        }
+        if let (Some(old_values), Some(new_values)) = (&old.values, &new.values) {
+            let added = new_values
+                .iter()
+                .filter(|v| !old_values.contains(v))
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            if !added.is_empty() {
+                self.push(
+                    path,
+                    ChangeKind::Cosmetic,
+                    format!("enum values {} observed", added.join(", ")),
+                );
+            }
+        }
+
+        if old.types.contains("object") && new.types.contains("object") {
+            self.diff_properties(path, old, new);
+        }
+        if let (Some(old_items), Some(new_items)) = (&old.items, &new.items) {
+            self.diff(&format!("{path}[]"), old_items, new_items);
+        }
+    }
+
+    fn diff_properties(&mut self, path: &str, old: &Shape, new: &Shape) {
+        let join = |name: &str| {
+            if path.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{path}.{name}")
+            }
+        };
+        let objects = self
+            .statistics
+            .and_then(|s| s.objects.get(path))
+            .copied()
+            .unwrap_or_default();
+        for (name, (old_property, old_required)) in old.properties.iter() {
+            let path = join(name);
+            match new.properties.get(name) {
+                // optional fields and small windows can miss a field that is still there
+                None if *old_required && objects >= MIN_OBJECTS => {
+                    self.push(&path, ChangeKind::Breaking, "field removed".to_owned())
+                }
+                None => {}
+                Some((new_property, new_required)) => {
+                    // a window having a field every time does not make it required
+                    if *old_required && !new_required {
+                        self.push(
+                            &path,
+                            ChangeKind::Additive,
+                            "required field became optional".to_owned(),
+                        );
+                    }
+                    self.diff(&path, old_property, new_property);
+                }
+            }
+        }
+        for (name, (_, required)) in new.properties.iter() {
+            if !old.properties.contains_key(name) {
+                let field = if *required { "required" } else { "optional" };
+                self.push(
+                    &join(name),
+                    ChangeKind::Additive,
+                    format!("{field} field added"),
+                );
+            }
+        }
+    }
+}
+
+impl EndpointSchemaChange {
+    /// Compares every status of the new datatypes with the latest stored schema of the same status,
+    /// before the statistics of the new datatypes are accumulated.
+    pub fn detect(
+        existing: &[EndpointDataType],
+        datatypes: &[EndpointDataType],
+    ) -> Vec<EndpointSchemaChange> {
+        let stored = EndpointDataType::latest_schemas(existing);
+
+        let mut results = vec![];
+        for datatype in datatypes.iter() {
+            for schema in datatype.schemas.iter() {
+                let key = (
+                    datatype.unique_endpoint_name.as_str(),
+                    schema.status.as_str(),
+                );
+                let Some(old) = stored.get(&key) else {
+                    continue;
+                };
+                let mut changes = Self::diff(
+                    SchemaBody::Request,
+                    body_schema(old.request_json_schema.as_ref(), &old.request_sample),
+                    body_schema(schema.request_json_schema.as_ref(), &schema.request_sample),
+                    schema.request_statistics.as_ref(),
+                );
+                changes.extend(Self::diff(
+                    SchemaBody::Response,
+                    body_schema(old.response_json_schema.as_ref(), &old.response_sample),
+                    body_schema(
+                        schema.response_json_schema.as_ref(),
+                        &schema.response_sample,
+                    ),
+                    schema.response_statistics.as_ref(),
+                ));
+                if let Some(kind) = changes.iter().map(|c| c.kind).max() {
+                    results.push(EndpointSchemaChange {
+                        unique_endpoint_name: datatype.unique_endpoint_name.clone(),
+                        status: schema.status.clone(),
+                        kind,
+                        changes,
+                    });
+                }
+            }
+        }
+        results
+    }
+
+    fn diff(
+        body: SchemaBody,
+        old: Option<Value>,
+        new: Option<Value>,
+        statistics: Option<&BodyStatistics>,
+    ) -> Vec<SchemaChange> {
+        let mut differ = Differ {
+            body,
+            statistics,
+            changes: vec![],
+        };
+        match (old, new) {
+            (Some(old), Some(new)) => differ.diff(
+                "",
+                &Shape::from_json_schema(&old),
+                &Shape::from_json_schema(&new),
+            ),
+            (None, Some(_)) => differ.push("", ChangeKind::Additive, "body added".to_owned()),
+            // a missing body is not evidence of a removed one
+            _ => {}
+        }
+        differ.changes
+    }
+}
+
+/// The stored JSON Schema, or one inferred from the sample for datatypes stored without it.
+fn body_schema(json_schema: Option<&Value>, sample: &Option<String>) -> Option<Value> {
+    let sample = sample
+        .as_deref()
+        .and_then(|s| serde_json::from_str::<Value>(s).ok());
+    match (json_schema, sample) {
+        (_, None) => None,
+        (_, Some(sample)) if is_empty_body(&sample) => None,
+        (Some(json_schema), _) => Some(json_schema.clone()),
+        (None, Some(sample)) => Some(json_schema::to_json_schema(&Schema::infer(&sample))),
+    }
+}
+
+#[test]
+fn test_detect() {
+    use super::{endpoint_data_type::EndpointDataSchema, request_type::RequestType};
+    use serde_json::json;
+
+    let datatype = |time: i64, requests: &[Value], responses: &[Value]| {
+        let json_schema =
+            |samples: &[Value]| json_schema::to_json_schema(&Schema::infer_all(samples));
+        let statistics = |samples: &[Value]| {
+            let mut statistics = BodyStatistics::default();
+            samples.iter().for_each(|s| statistics.add(s));
+            statistics
+        };
+        EndpointDataType {
+            _id: None,
+            unique_service_name: "svc\tns\tv1".to_owned(),
+            unique_endpoint_name: "svc\tns\tv1\tPOST\thttp://svc/a".to_owned(),
+            service: "svc".to_owned(),
+            namespace: "ns".to_owned(),
+            version: "v1".to_owned(),
+            label_name: None,
+            method: RequestType::Post,
+            schemas: vec![EndpointDataSchema {
+                time,
+                status: "200".to_owned(),
+                request_sample: Some(requests[0].to_string()),
+                request_schema: None,
+                request_json_schema: Some(json_schema(requests)),
+                request_content_type: None,
+                request_params: None,
+                response_sample: Some(responses[0].to_string()),
+                response_schema: None,
+                response_json_schema: Some(json_schema(responses)),
+                request_statistics: Some(statistics(requests)),
+                response_statistics: Some(statistics(responses)),
+                response_content_type: None,
+            }],
+        }
+    };
+    let kinds = |changes: &[EndpointSchemaChange]| {
+        changes
+            .iter()
+            .flat_map(|c| c.changes.iter())
+            .map(|c| (c.body, c.path.clone(), c.kind, c.description.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    // every window so far: "tag" is optional, "state" is on or off
+    let responses = (0..20)
+        .map(|i| {
+            let mut body =
+                json!({ "id": i, "user": { "zip": i.to_string() }, "state": (["on", "off"][i % 2]) });
+            if i % 2 == 0 {
+                body["tag"] = json!("a");
+            }
+            body
+        })
+        .collect::<Vec<_>>();
+    let old = datatype(0, &[json!({ "name": "a" })], &responses);
+
+    let responses = (0..12)
+        .map(|i| json!({ "id": i.to_string(), "user": {}, "state": (["on", "paused"][i % 2]), "tag": "a" }))
+        .collect::<Vec<_>>();
+    let new = datatype(1, &[json!({ "name": "a", "age": 1 })], &responses);
+    let changes = EndpointSchemaChange::detect(std::slice::from_ref(&old), &[new]);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].status, "200");
+    assert_eq!(changes[0].kind, ChangeKind::Breaking);
+    let response = |path: &str, kind, description: &str| {
+        (
+            SchemaBody::Response,
+            path.to_owned(),
+            kind,
+            description.to_owned(),
+        )
+    };
+    // "tag" seen in every body of the window stays optional
+    assert_eq!(
+        kinds(&changes),
+        vec![
+            (
+                SchemaBody::Request,
+                "age".to_owned(),
+                ChangeKind::Additive,
+                "required field added".to_owned()
+            ),
+            response(
+                "id",
+                ChangeKind::Breaking,
+                "type changed from number to string"
+            ),
+            response(
+                "state",
+                ChangeKind::Cosmetic,
+                "enum values \"paused\" observed"
+            ),
+            response("user.zip", ChangeKind::Breaking, "field removed"),
+        ]
+    );
+
+    // a few bodies without a required field or rare enum values are not changes
+    let responses = [json!({ "id": 3, "user": {}, "state": "on" })];
+    let few = datatype(2, &[json!({ "name": "b" })], &responses);
+    assert!(EndpointSchemaChange::detect(std::slice::from_ref(&old), &[few]).is_empty());
+
+    // renamed definitions and reordered samples are not changes
+    let responses = (0..12)
+        .map(|i| json!({ "id": i, "user": { "zip": i.to_string() }, "state": (["off", "on"][i % 2]) }))
+        .collect::<Vec<_>>();
+    let same = datatype(3, &[json!({ "name": "b" })], &responses);
+    assert!(EndpointSchemaChange::detect(&[old], &[same]).is_empty());
+}
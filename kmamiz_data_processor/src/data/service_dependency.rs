@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    endpoint_dependency::{EndpointDependency, EndpointDependencyType},
+    endpoint_info::EndpointInfo,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceDependency {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    // endpoint dependencies of the service's own endpoints
+    pub dependency: Vec<EndpointDependency>,
+    pub links: Vec<ServiceLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLink {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    // links to the owning service itself, calls between its own endpoints
+    pub is_self: bool,
+    // distinct endpoint dependencies behind this link
+    pub count: usize,
+    pub depending_by: usize,
+    pub depending_on: usize,
+    // observed calls on direct endpoint dependencies
+    pub calls: u64,
+    pub details: Vec<ServiceLinkDetail>,
+    pub endpoints: Vec<ServiceLinkEndpoint>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLinkDetail {
+    pub distance: u32,
+    pub count: usize,
+    pub depending_by: usize,
+    pub depending_on: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceLinkEndpoint {
+    // endpoint of the service owning the link
+    pub endpoint: String,
+    // endpoint of the linked service
+    pub dependency: String,
+    pub distance: u32,
+    pub r#type: EndpointDependencyType,
+    pub calls: u64,
+}
+
+impl ServiceDependency {
+    pub fn from_endpoint_dependencies(
+        dependencies: &[EndpointDependency],
+    ) -> Vec<ServiceDependency> {
+        let mut services: BTreeMap<&str, ServiceAccumulator> = BTreeMap::new();
+        // (owning service, linked endpoint, type, distance), links are counted once per endpoint
+        let mut counted = BTreeSet::new();
+
+        for dep in dependencies.iter() {
+            let service = services
+                .entry(&dep.endpoint.unique_service_name)
+                .or_insert_with(|| ServiceAccumulator {
+                    info: &dep.endpoint,
+                    dependency: vec![],
+                    links: BTreeMap::new(),
+                });
+            service.dependency.push(dep.clone());
+            let links = &mut service.links;
+
+            for item in dep.depending_on.iter().chain(dep.depending_by.iter()) {
+                let target = &item.endpoint;
+                let link =
+                    links
+                        .entry(&target.unique_service_name)
+                        .or_insert_with(|| ServiceLink {
+                            unique_service_name: target.unique_service_name.clone(),
+                            service: target.service.clone(),
+                            namespace: target.namespace.clone(),
+                            version: target.version.clone(),
+                            is_self: target.unique_service_name == dep.endpoint.unique_service_name,
+                            count: 0,
+                            depending_by: 0,
+                            depending_on: 0,
+                            calls: 0,
+                            details: vec![],
+                            endpoints: vec![],
+                        });
+
+                let calls = item.metrics.as_ref().map(|m| m.calls).unwrap_or_default();
+                link.calls += calls;
+                link.endpoints.push(ServiceLinkEndpoint {
+                    endpoint: dep.endpoint.unique_endpoint_name.clone(),
+                    dependency: target.unique_endpoint_name.clone(),
+                    distance: item.distance,
+                    r#type: item.r#type.clone(),
+                    calls,
+                });
+
+                let id = (
+                    &dep.endpoint.unique_service_name,
+                    &target.unique_endpoint_name,
+                    item.r#type.clone(),
+                    item.distance,
+                );
+                if !counted.insert(id) {
+                    continue;
+                }
+                let (by, on) = match item.r#type {
+                    EndpointDependencyType::Client => (1, 0),
+                    EndpointDependencyType::Server => (0, 1),
+                };
+                link.count += 1;
+                link.depending_by += by;
+                link.depending_on += on;
+                match link
+                    .details
+                    .iter_mut()
+                    .find(|d| d.distance == item.distance)
+                {
+                    Some(detail) => {
+                        detail.count += 1;
+                        detail.depending_by += by;
+                        detail.depending_on += on;
+                    }
+                    None => link.details.push(ServiceLinkDetail {
+                        distance: item.distance,
+                        count: 1,
+                        depending_by: by,
+                        depending_on: on,
+                    }),
+                }
+            }
+        }
+
+        services
+            .into_values()
+            .map(
+                |ServiceAccumulator {
+                     info,
+                     dependency,
+                     links,
+                 }| ServiceDependency {
+                    unique_service_name: info.unique_service_name.clone(),
+                    service: info.service.clone(),
+                    namespace: info.namespace.clone(),
+                    version: info.version.clone(),
+                    dependency,
+                    links: links
+                        .into_values()
+                        .map(|mut link| {
+                            link.details.sort_by_key(|d| d.distance);
+                            link.endpoints.sort_by(|a, b| {
+                                (&a.endpoint, &a.dependency, a.distance).cmp(&(
+                                    &b.endpoint,
+                                    &b.dependency,
+                                    b.distance,
+                                ))
+                            });
+                            link
+                        })
+                        .collect(),
+                },
+            )
+            .collect()
+    }
+}
+
+struct ServiceAccumulator<'a> {
+    info: &'a EndpointInfo,
+    dependency: Vec<EndpointDependency>,
+    links: BTreeMap<&'a str, ServiceLink>,
+}
+
+#[test]
+fn test_service_dependencies() {
+    use crate::data::fixtures::{dependencies, endpoint, URL_A, URL_A2, URL_B, URL_C};
+
+    let (endpoint_a, endpoint_a2, endpoint_b, endpoint_c) = (
+        endpoint(URL_A),
+        endpoint(URL_A2),
+        endpoint(URL_B),
+        endpoint(URL_C),
+    );
+    // A -> B -> C, A2 -> B
+    let services = ServiceDependency::from_endpoint_dependencies(&dependencies(&[
+        (&endpoint_a, &endpoint_b, 1),
+        (&endpoint_b, &endpoint_c, 1),
+        (&endpoint_a, &endpoint_c, 2),
+        (&endpoint_a2, &endpoint_b, 1),
+    ]));
+    let names = services
+        .iter()
+        .map(|s| s.service.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["svc-a", "svc-b", "svc-c"]);
+
+    let a = &services[0];
+    assert_eq!(a.links.len(), 2);
+    let a_b = &a.links[0];
+    assert_eq!(a_b.service, "svc-b");
+    assert_eq!((a_b.count, a_b.depending_on, a_b.depending_by), (1, 1, 0));
+    assert_eq!(a_b.endpoints.len(), 2);
+    assert_eq!(a_b.calls, 2);
+    let a_c = &a.links[1];
+    assert_eq!(a_c.service, "svc-c");
+    assert_eq!(a_c.details[0].distance, 2);
+
+    let b = &services[1];
+    let b_a = &b.links[0];
+    assert_eq!(b_a.service, "svc-a");
+    assert_eq!((b_a.count, b_a.depending_on, b_a.depending_by), (2, 0, 2));
+    assert!(services
+        .iter()
+        .flat_map(|s| s.links.iter())
+        .all(|l| !l.is_self));
+    // the endpoint dependencies of the service, as TServiceDependency.dependency
+    assert_eq!(a.dependency.len(), 2);
+    assert!(a
+        .dependency
+        .iter()
+        .all(|d| d.endpoint.unique_service_name == a.unique_service_name));
+
+    // calls between endpoints of the same service are marked
+    let services = ServiceDependency::from_endpoint_dependencies(&dependencies(&[(
+        &endpoint_a,
+        &endpoint_a2,
+        1,
+    )]));
+    assert_eq!(services.len(), 1);
+    assert_eq!(services[0].links.len(), 1);
+    assert!(services[0].links[0].is_self);
+}
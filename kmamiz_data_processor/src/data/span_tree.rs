@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::trace::Trace;
+
+/// Spans of a single trace linked by parent, keyed by (trace_id, span_id).
+#[derive(Debug)]
+pub struct SpanTree<'a> {
+    pub trace_id: &'a str,
+    pub nodes: Vec<SpanNode<'a>>,
+    // nodes without a parent, orphans included
+    pub roots: Vec<usize>,
+    // nodes whose parent is missing from the trace, or whose parent chain loops back on itself
+    pub orphans: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct SpanNode<'a> {
+    pub span: &'a Trace,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // corrected for clock skew, in microseconds
+    pub timestamp: u64,
+}
+
+impl<'a> SpanTree<'a> {
+    pub fn from_traces(traces: &'a [Vec<Trace>]) -> Vec<SpanTree<'a>> {
+        traces.iter().flat_map(|t| Self::build(t)).collect()
+    }
+
+    /// Builds one tree per trace ID found in the spans.
+    pub fn build(spans: &'a [Trace]) -> Vec<SpanTree<'a>> {
+        let mut order = vec![];
+        let mut traces: HashMap<&str, Vec<&Trace>> = HashMap::new();
+        for span in spans.iter() {
+            let entry = traces.entry(&span.trace_id).or_default();
+            if entry.is_empty() {
+                order.push(span.trace_id.as_str());
+            }
+            entry.push(span);
+        }
+        order
+            .into_iter()
+            .map(|trace_id| Self::build_trace(trace_id, traces.remove(trace_id).unwrap()))
+            .collect()
+    }
+
+    fn build_trace(trace_id: &'a str, spans: Vec<&'a Trace>) -> SpanTree<'a> {
+        // with shared spans the CLIENT and SERVER side of a call have the same ID,
+        // children then belong to the SERVER side and the SERVER side to the CLIENT side
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        let mut clients: HashMap<&str, usize> = HashMap::new();
+        for (i, span) in spans.iter().enumerate() {
+            if span.kind == *"CLIENT" {
+                clients.insert(&span.id, i);
+            }
+            match index.get(span.id.as_str()) {
+                Some(&j) if spans[j].kind == *"SERVER" => {}
+                _ => {
+                    index.insert(&span.id, i);
+                }
+            }
+        }
+
+        let mut nodes = spans
+            .iter()
+            .map(|span| SpanNode {
+                span,
+                parent: None,
+                children: vec![],
+                timestamp: span.timestamp,
+            })
+            .collect::<Vec<_>>();
+        let mut orphans = vec![];
+        for (i, node) in nodes.iter_mut().enumerate() {
+            let span = node.span;
+            let shared = clients.get(span.id.as_str()).copied();
+            node.parent = match (span.kind.as_str(), shared, span.parent_id.as_deref()) {
+                ("SERVER", Some(client), _) if client != i => Some(client),
+                (_, _, None) => None,
+                (_, _, Some(parent_id)) => match index.get(parent_id) {
+                    Some(&p) if parent_id != span.id => Some(p),
+                    _ => {
+                        orphans.push(i);
+                        None
+                    }
+                },
+            };
+        }
+
+        for i in 0..nodes.len() {
+            let mut visited = HashSet::from([i]);
+            let mut current = i;
+            while let Some(parent) = nodes[current].parent {
+                if !visited.insert(parent) {
+                    nodes[current].parent = None;
+                    orphans.push(current);
+                    break;
+                }
+                current = parent;
+            }
+        }
+        orphans.sort_unstable();
+        orphans.dedup();
+
+        let mut roots = vec![];
+        for i in 0..nodes.len() {
+            match nodes[i].parent {
+                Some(parent) => nodes[parent].children.push(i),
+                None => roots.push(i),
+            }
+        }
+
+        let mut tree = SpanTree {
+            trace_id,
+            nodes,
+            roots,
+            orphans,
+        };
+        tree.correct_skew();
+        tree
+    }
+
+    /// Parents of the node, nearest first.
+    pub fn ancestors(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.nodes[i].parent, |&p| self.nodes[p].parent)
+    }
+
+    /// Shifts every SERVER span that lies outside of the CLIENT span calling it into the middle
+    /// of the client's interval, spans below it on the same host move along with it.
+    fn correct_skew(&mut self) {
+        let mut shifts = vec![0i64; self.nodes.len()];
+        let mut queue = self.roots.iter().map(|&r| (r, 0)).collect::<VecDeque<_>>();
+        while let Some((i, inherited)) = queue.pop_front() {
+            let shift = self.rpc_shift(i, &shifts).unwrap_or(inherited);
+            shifts[i] = shift;
+            let node = &mut self.nodes[i];
+            node.timestamp = (node.span.timestamp as i64 + shift).max(0) as u64;
+            queue.extend(node.children.iter().map(|&c| (c, shift)));
+        }
+    }
+
+    /// Shift of a SERVER span relative to its caller, using the cs/sr/ss/cr annotations if present.
+    fn rpc_shift(&self, i: usize, shifts: &[i64]) -> Option<i64> {
+        let annotation = |span: &Trace, value: &str| {
+            span.annotations
+                .iter()
+                .find(|a| a.value == value)
+                .map(|a| a.timestamp as i64)
+        };
+        let server = self.nodes[i].span;
+        if server.kind != *"SERVER" {
+            return None;
+        }
+        let sr = annotation(server, "sr").unwrap_or(server.timestamp as i64);
+        let ss = annotation(server, "ss").unwrap_or((server.timestamp + server.duration) as i64);
+        let parent = self.nodes[i].parent;
+        // cs and cr are on the caller's clock, which may have been corrected already
+        let caller_shift = parent.map(|p| shifts[p]).unwrap_or_default();
+        let (cs, cr) = match (annotation(server, "cs"), annotation(server, "cr")) {
+            (Some(cs), Some(cr)) => (cs + caller_shift, cr + caller_shift),
+            _ => {
+                let client = self.nodes[parent?].span;
+                if client.kind != *"CLIENT" {
+                    return None;
+                }
+                let cs = annotation(client, "cs").unwrap_or(client.timestamp as i64);
+                let cr =
+                    annotation(client, "cr").unwrap_or((client.timestamp + client.duration) as i64);
+                (cs + caller_shift, cr + caller_shift)
+            }
+        };
+        if sr >= cs && ss <= cr {
+            return Some(0);
+        }
+        // network latency each way, assumed symmetric
+        let latency = (((cr - cs) - (ss - sr)) / 2).max(0);
+        Some(cs + latency - sr)
+    }
+}
+
+#[test]
+fn test_span_tree() {
+    use crate::data::fixtures::{client, server, URL_A, URL_B, URL_C};
+    let mut spans = vec![
+        server("1", None, URL_A, 1_000, 100),
+        client("2", Some("1"), URL_B, 1_010, 80),
+        // svc-b's clock is 500 behind
+        server("3", Some("2"), URL_B, 520, 60),
+        client("4", Some("3"), URL_C, 530, 10),
+        server("5", Some("missing"), URL_C, 2_000, 10),
+        // same span ID in another trace
+        server("1", None, URL_C, 3_000, 10),
+    ];
+    spans[5].trace_id = "other".to_owned();
+
+    let trees = SpanTree::build(&spans);
+    assert_eq!(trees.len(), 2);
+    let tree = &trees[0];
+    assert_eq!(tree.roots, vec![0, 4]);
+    assert_eq!(tree.orphans, vec![4]);
+    assert_eq!(tree.ancestors(3).collect::<Vec<_>>(), vec![2, 1, 0]);
+    // centered into the client's 80 microseconds, the child on the same host moves along
+    assert_eq!(tree.nodes[2].timestamp, 1_020);
+    assert_eq!(tree.nodes[3].timestamp, 1_030);
+    assert_eq!(tree.nodes[4].timestamp, 2_000);
+    assert_eq!(trees[1].trace_id, "other");
+    assert_eq!(trees[1].nodes.len(), 1);
+
+    // shared span: the SERVER side reuses the CLIENT span ID and carries the annotations
+    let mut shared_client = client("2", Some("1"), URL_B, 1_010, 80);
+    shared_client.annotations = vec![];
+    let mut shared_server = server("2", Some("1"), URL_B, 5_000, 60);
+    shared_server.annotations = [("cs", 1_010), ("sr", 5_000), ("ss", 5_060), ("cr", 1_090)]
+        .into_iter()
+        .map(|(value, timestamp)| super::trace::Annotation {
+            timestamp,
+            value: value.to_owned(),
+        })
+        .collect();
+    let child = client("6", Some("2"), URL_C, 5_010, 10);
+    let spans = vec![spans[0].clone(), shared_client, shared_server, child];
+    let tree = &SpanTree::build(&spans)[0];
+    assert_eq!(tree.nodes[2].parent, Some(1));
+    assert_eq!(tree.nodes[3].parent, Some(2));
+    assert_eq!(tree.nodes[2].timestamp, 1_020);
+    assert!(tree.orphans.is_empty());
+
+    // shared span under a skewed caller, cs and cr move along with the caller's correction
+    let mut shared_client = client("7", Some("3"), URL_C, 530, 40);
+    shared_client.annotations = vec![];
+    let mut shared_server = server("7", Some("3"), URL_C, 9_000, 20);
+    shared_server.annotations = [("cs", 530), ("sr", 9_000), ("ss", 9_020), ("cr", 570)]
+        .into_iter()
+        .map(|(value, timestamp)| super::trace::Annotation {
+            timestamp,
+            value: value.to_owned(),
+        })
+        .collect();
+    let mut spans = vec![spans[0].clone(), spans[1].clone()];
+    spans.push(server("3", Some("2"), URL_B, 520, 60));
+    spans.extend([shared_client, shared_server]);
+    let tree = &SpanTree::build(&spans)[0];
+    assert_eq!(tree.nodes[2].timestamp, 1_020);
+    assert_eq!(tree.nodes[3].timestamp, 1_030);
+    assert_eq!(tree.nodes[4].parent, Some(3));
+    // same as without a shared span, centered into the shifted 1_030..1_070
+    assert_eq!(tree.nodes[4].timestamp, 1_040);
+}
+
+#[test]
+fn test_span_ids_reused_across_traces() {
+    use crate::data::fixtures::{server, URL_A, URL_B, URL_C};
+    use crate::http_client::url_matcher::UrlMatcher;
+
+    // A -> B in one trace, C -> B in another, with the same span IDs
+    let mut other = vec![
+        server("1", None, URL_C, 0, 100),
+        server("2", Some("1"), URL_B, 10, 50),
+    ];
+    other
+        .iter_mut()
+        .for_each(|s| s.trace_id = "other".to_owned());
+    let traces = vec![
+        vec![
+            server("1", None, URL_A, 0, 100),
+            server("2", Some("1"), URL_B, 10, 50),
+        ],
+        other,
+    ];
+
+    let dependencies = Trace::to_endpoint_dependencies(&traces, &UrlMatcher::new());
+    let mut callers = dependencies
+        .iter()
+        .filter(|d| d.endpoint.service == "svc-b")
+        .flat_map(|d| d.depending_by.iter().map(|c| c.endpoint.service.as_str()))
+        .collect::<Vec<_>>();
+    callers.sort();
+    assert_eq!(callers, vec!["svc-a", "svc-c"]);
+}
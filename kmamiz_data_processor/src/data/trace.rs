@@ -1,5 +1,4 @@
 use std::{
-    cell::RefCell,
     collections::{HashMap, HashSet},
     str::FromStr,
 };
@@ -9,11 +8,16 @@ use serde::{Deserialize, Serialize};
 use crate::{data::request_type::RequestType, http_client::url_matcher::UrlMatcher};
 
 use super::{
-    endpoint_dependency::{EndpointDependency, EndpointDependencyItem, EndpointDependencyType},
+    endpoint_dependency::{
+        EndpointDependency, EndpointDependencyItem, EndpointDependencyMetrics,
+        EndpointDependencyType,
+    },
     endpoint_info::EndpointInfo,
     envoy_log::StructuredEnvoyLog,
     realtime_data::RealtimeData,
     replica_count::ReplicaCount,
+    sampling::SamplingConfig,
+    span_tree::SpanTree,
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,13 +27,21 @@ pub struct Trace {
     pub trace_id: String,
     pub parent_id: Option<String>,
     pub id: String,
+    // empty for local spans
+    #[serde(default)]
     pub kind: String,
     pub name: String,
     pub timestamp: u64,
     pub duration: u64,
+    #[serde(default)]
     pub local_endpoint: LocalEndpoint,
+    #[serde(default)]
     pub annotations: Vec<Annotation>,
+    #[serde(default)]
     pub tags: Tags,
+    // already counted in a previous round, the trace is processed again because of late spans
+    #[serde(skip)]
+    pub processed: bool,
 }
 
 impl Trace {
@@ -38,6 +50,8 @@ impl Trace {
             .iter()
             .flatten()
             .map(|t| t.tags.istio_namespace.to_string())
+            // spans not recorded by istio, e.g. from messaging client libraries
+            .filter(|n| !n.is_empty())
             .collect()
     }
 
@@ -45,6 +59,7 @@ impl Trace {
         traces: &[Vec<Trace>],
         s_logs: Vec<StructuredEnvoyLog>,
         replicas: &[ReplicaCount],
+        sampling: &SamplingConfig,
     ) -> Vec<RealtimeData> {
         let mut replica_map = HashMap::new();
         for replica in replicas.iter() {
@@ -67,12 +82,13 @@ impl Trace {
         traces
             .iter()
             .flatten()
-            .filter(|t| t.kind == "SERVER")
-            .map(|trace| -> RealtimeData {
+            .filter(|t| t.kind == "SERVER" && !t.processed)
+            .filter_map(|trace| -> Option<RealtimeData> {
+                // SERVER spans of other protocols are not endpoints
+                let method = RequestType::from_str(trace.tags.http_method.as_str()).ok()?;
                 let service = trace.tags.istio_canonical_service.clone();
                 let namespace = trace.tags.istio_namespace.clone();
                 let version = trace.tags.istio_canonical_revision.clone();
-                let method = RequestType::from_str(trace.tags.http_method.as_str()).unwrap();
                 let status = trace.tags.http_status_code.clone();
                 let unique_service_name = format!("{service}\t{namespace}\t{version}");
 
@@ -84,7 +100,7 @@ impl Trace {
                         .and_then(|t| t.get(&trace.parent_id.as_ref().unwrap()));
                 }
 
-                RealtimeData {
+                Some(RealtimeData {
                     timestamp: trace.timestamp as i64,
                     service,
                     namespace,
@@ -101,8 +117,9 @@ impl Trace {
                         trace.tags.http_method, trace.tags.http_url
                     ),
                     replica: replica_map.get(&unique_service_name).copied(),
+                    sampling_rate: sampling.rate_of(trace),
                     unique_service_name,
-                }
+                })
             })
             .collect()
     }
@@ -111,107 +128,177 @@ impl Trace {
         traces: &[Vec<Trace>],
         url_matcher: &UrlMatcher,
     ) -> Vec<EndpointDependency> {
-        let mut span_dep_depth = HashMap::new();
-        for span in traces.iter().flatten() {
-            span_dep_depth.insert(
-                &span.id,
-                SpanDependency {
-                    span,
-                    upper: RefCell::new(HashMap::new()),
-                    lower: RefCell::new(HashMap::new()),
-                },
-            );
-        }
-
-        let mut endpoint_info_map = HashMap::new();
-        for (span_id, dep) in span_dep_depth.iter() {
-            endpoint_info_map.insert(span_id.to_string(), dep.span.to_endpoint_info(url_matcher));
-        }
-
-        span_dep_depth
+        SpanTree::from_traces(traces)
             .iter()
-            .filter(|(_, v)| v.span.kind == *"SERVER")
-            .for_each(|(span_id, dep)| {
-                let mut parent_id = &dep.span.parent_id;
-                let mut depth = 1;
-                while let Some(id) = parent_id.as_ref() {
-                    let parent_node = span_dep_depth.get(id);
-                    if parent_node.is_none() {
-                        break;
+            .flat_map(|tree| Self::tree_to_endpoint_dependencies(tree, url_matcher))
+            .collect()
+    }
+
+    fn tree_to_endpoint_dependencies(
+        tree: &SpanTree,
+        url_matcher: &UrlMatcher,
+    ) -> Vec<EndpointDependency> {
+        let nodes = &tree.nodes;
+        let endpoint_info_map = (0..nodes.len())
+            .filter(|i| nodes[*i].span.kind == *"SERVER")
+            .filter_map(|i| Some((i, nodes[i].span.to_endpoint_info(url_matcher)?)))
+            .collect::<HashMap<_, _>>();
+        let mut servers = endpoint_info_map.keys().copied().collect::<Vec<_>>();
+        servers.sort();
+
+        // the span measuring a direct call to a SERVER span, the CLIENT span if there is one
+        let mut edge_spans = HashMap::new();
+        let mut upper: HashMap<usize, HashMap<usize, u32>> = HashMap::new();
+        let mut lower: HashMap<usize, HashMap<usize, u32>> = HashMap::new();
+        for &i in servers.iter() {
+            edge_spans.insert(i, nodes[i].span);
+            let mut depth = 1;
+            for parent in tree.ancestors(i) {
+                match nodes[parent].span.kind.as_str() {
+                    // SERVER spans without an endpoint are passed through like local spans
+                    "SERVER" if !endpoint_info_map.contains_key(&parent) => {}
+                    "SERVER" => {
+                        // pairs of spans both counted in a previous round are not counted again
+                        if !nodes[i].span.processed || !nodes[parent].span.processed {
+                            upper.entry(i).or_default().insert(parent, depth);
+                            lower.entry(parent).or_default().insert(i, depth);
+                        }
+                        depth += 1;
                     }
-                    let parent_node = parent_node.unwrap();
-                    if parent_node.span.kind == *"CLIENT" {
-                        parent_id = &parent_node.span.parent_id;
-                        continue;
+                    "CLIENT" => {
+                        if depth == 1 {
+                            edge_spans.insert(i, nodes[parent].span);
+                        }
                     }
-                    dep.upper
-                        .borrow_mut()
-                        .insert(parent_node.span.id.clone(), depth);
-                    parent_node
-                        .lower
-                        .borrow_mut()
-                        .insert(span_id.to_string(), depth);
-                    parent_id = &parent_node.span.parent_id;
-                    depth += 1;
+                    // local spans
+                    "" => {}
+                    // PRODUCER/CONSUMER spans are asynchronous boundaries, see MessagingDependency
+                    _ => break,
                 }
-            });
-
-        let mut dependencies = vec![];
-        for (_, dep) in span_dep_depth
-            .into_iter()
-            .filter(|(_, v)| v.span.kind == *"SERVER")
-        {
-            let upper_map = Self::to_info_map(&dep.upper, &endpoint_info_map);
-            let lower_map = Self::to_info_map(&dep.lower, &endpoint_info_map);
-
-            let depending_by = Self::to_depending(upper_map, EndpointDependencyType::Client);
-            let depending_on = Self::to_depending(lower_map, EndpointDependencyType::Server);
-
-            dependencies.push(EndpointDependency {
-                endpoint: dep.span.to_endpoint_info(url_matcher),
-                depending_by,
-                depending_on,
-                _id: None,
-            });
+            }
         }
 
-        dependencies
+        let empty = HashMap::new();
+        servers
+            .iter()
+            .filter(|i| {
+                !nodes[**i].span.processed || upper.contains_key(*i) || lower.contains_key(*i)
+            })
+            .map(|&i| {
+                // a dependency is last seen when the later of the two spans started
+                let last_seen = |j: usize| nodes[j].timestamp.max(nodes[i].timestamp);
+                let upper_map = Self::to_info_map(
+                    upper.get(&i).unwrap_or(&empty),
+                    &endpoint_info_map,
+                    |_| edge_spans.get(&i).copied(),
+                    last_seen,
+                );
+                let lower_map = Self::to_info_map(
+                    lower.get(&i).unwrap_or(&empty),
+                    &endpoint_info_map,
+                    |j| edge_spans.get(&j).copied(),
+                    last_seen,
+                );
+
+                EndpointDependency {
+                    endpoint: endpoint_info_map[&i].clone(),
+                    depending_by: Self::to_depending(upper_map, EndpointDependencyType::Client),
+                    depending_on: Self::to_depending(lower_map, EndpointDependencyType::Server),
+                    last_seen: Some(nodes[i].timestamp),
+                    _id: None,
+                }
+            })
+            .collect()
     }
 
     fn to_info_map<'a>(
-        dep: &'a RefCell<HashMap<String, u32>>,
-        endpoint_info_map: &'a HashMap<String, EndpointInfo>,
-    ) -> HashMap<String, &'a EndpointInfo> {
-        let mut map = HashMap::new();
-
-        dep.borrow().iter().for_each(|(s, dist)| {
-            let endpoint = endpoint_info_map.get(s).unwrap();
-            map.insert(
-                format!("{}\t{dist}", endpoint.unique_endpoint_name),
-                endpoint,
-            );
+        dep: &HashMap<usize, u32>,
+        endpoint_info_map: &'a HashMap<usize, EndpointInfo>,
+        edge_span: impl Fn(usize) -> Option<&'a Trace>,
+        last_seen: impl Fn(usize) -> u64,
+    ) -> HashMap<String, DependingInfo<'a>> {
+        let mut map: HashMap<String, DependingInfo> = HashMap::new();
+
+        dep.iter().for_each(|(&s, dist)| {
+            let endpoint = &endpoint_info_map[&s];
+            let metrics = if *dist == 1 {
+                edge_span(s).map(EndpointDependencyMetrics::from_span)
+            } else {
+                None
+            };
+            let entry = map
+                .entry(format!("{}\t{dist}", endpoint.unique_endpoint_name))
+                .or_insert(DependingInfo {
+                    endpoint,
+                    metrics: None,
+                    last_seen: 0,
+                });
+            entry.metrics = EndpointDependencyMetrics::merge_option(entry.metrics.take(), metrics);
+            entry.last_seen = entry.last_seen.max(last_seen(s));
         });
         map
     }
 
     fn to_depending(
-        map: HashMap<String, &EndpointInfo>,
+        map: HashMap<String, DependingInfo>,
         r#type: EndpointDependencyType,
     ) -> Vec<EndpointDependencyItem> {
         map.into_iter()
-            .map(|(id, endpoint)| {
-                let token = id.split('\t');
-                let distance = u32::from_str(token.last().unwrap_or("")).unwrap_or(0);
+            .map(|(id, info)| {
+                let mut token = id.split('\t');
+                let distance = u32::from_str(token.next_back().unwrap_or("")).unwrap_or(0);
                 EndpointDependencyItem {
-                    endpoint: endpoint.clone(),
+                    endpoint: info.endpoint.clone(),
                     distance,
                     r#type: r#type.clone(),
+                    metrics: info.metrics,
+                    last_seen: Some(info.last_seen),
                 }
             })
             .collect()
     }
 
-    pub fn to_endpoint_info(&self, url_matcher: &UrlMatcher) -> EndpointInfo {
+    /// (system, destination) of a PRODUCER or CONSUMER span, the topic or queue the message went through.
+    pub fn to_messaging_destination(&self) -> Option<(String, String)> {
+        if self.kind != *"PRODUCER" && self.kind != *"CONSUMER" {
+            return None;
+        }
+        let tags = &self.tags;
+        let system = tags.messaging_system.clone().or_else(|| {
+            tags.kafka_topic
+                .as_ref()
+                .map(|_| "kafka".to_owned())
+                .or_else(|| tags.rabbitmq_queue.as_ref().map(|_| "rabbitmq".to_owned()))
+        });
+        let destination = tags
+            .messaging_destination
+            .clone()
+            .or_else(|| tags.kafka_topic.clone())
+            .or_else(|| tags.rabbitmq_queue.clone())
+            .filter(|d| !d.is_empty())?;
+        Some((system.unwrap_or_else(|| "unknown".to_owned()), destination))
+    }
+
+    /// (service, namespace, version), falls back to the local endpoint for spans not recorded by istio.
+    pub fn to_service_info(&self) -> (String, String, String) {
+        let tags = &self.tags;
+        let service = if tags.istio_canonical_service.is_empty() {
+            self.local_endpoint.service_name.clone()
+        } else {
+            tags.istio_canonical_service.clone()
+        };
+        let version = if tags.istio_canonical_revision.is_empty() {
+            "NONE".to_owned()
+        } else {
+            tags.istio_canonical_revision.clone()
+        };
+        (service, tags.istio_namespace.clone(), version)
+    }
+
+    /// None if the span has no HTTP method, e.g. SERVER spans of non-HTTP instrumentations.
+    pub fn to_endpoint_info(&self, url_matcher: &UrlMatcher) -> Option<EndpointInfo> {
+        let method = &self.tags.http_method;
+        let request_type = RequestType::from_str(method).ok()?;
         let url = url_matcher.explode_url(&self.tags.http_url, false);
         let mut service_url = url_matcher.explode_url(&self.name, true);
         if !self.name.contains(".svc.") {
@@ -233,7 +320,6 @@ impl Trace {
         );
 
         let http_url = &self.tags.http_url;
-        let method = &self.tags.http_method;
         let port = url.port.unwrap_or_default();
         let port = if port.is_empty() {
             "80".to_owned()
@@ -241,7 +327,7 @@ impl Trace {
             port
         };
 
-        EndpointInfo {
+        Some(EndpointInfo {
             version,
             service: service_url.service_name.unwrap_or_default(),
             namespace: service_url.namespace.unwrap_or_default(),
@@ -250,16 +336,16 @@ impl Trace {
             path: url.path.unwrap_or_default(),
             port,
             cluster_name: service_url.cluster_name.unwrap_or_default(),
-            method: RequestType::from_str(method).expect("cannot parse http method"),
+            method: request_type,
             unique_endpoint_name: format!("{unique_service_name}\t{method}\t{http_url}"),
             unique_service_name,
             label_name: None,
-        }
+        })
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
 pub struct LocalEndpoint {
     pub service_name: String,
     pub ipv4: String,
@@ -272,8 +358,9 @@ pub struct Annotation {
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
+// every tag is optional, spans from messaging libraries carry none of the http or istio ones
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
 pub struct Tags {
     pub component: String,
 
@@ -296,11 +383,67 @@ pub struct Tags {
     pub istio_mesh_id: String,
     #[serde(rename = "istio.namespace")]
     pub istio_namespace: String,
+
+    #[serde(rename = "messaging.system")]
+    pub messaging_system: Option<String>,
+    #[serde(rename = "messaging.destination.name", alias = "messaging.destination")]
+    pub messaging_destination: Option<String>,
+    #[serde(rename = "kafka.topic")]
+    pub kafka_topic: Option<String>,
+    #[serde(rename = "rabbitmq.queue")]
+    pub rabbitmq_queue: Option<String>,
+
+    #[serde(rename = "sampler.type")]
+    pub sampler_type: Option<String>,
+    #[serde(rename = "sampler.param")]
+    pub sampler_param: Option<String>,
+
+    #[serde(rename = "db.system")]
+    pub db_system: Option<String>,
+    #[serde(rename = "db.name")]
+    pub db_name: Option<String>,
+    #[serde(rename = "db.statement")]
+    pub db_statement: Option<String>,
+    #[serde(rename = "db.operation")]
+    pub db_operation: Option<String>,
+    #[serde(rename = "db.sql.table")]
+    pub db_sql_table: Option<String>,
+    #[serde(rename = "db.mongodb.collection")]
+    pub db_mongodb_collection: Option<String>,
+    #[serde(rename = "server.address", alias = "net.peer.name")]
+    pub server_address: Option<String>,
+    #[serde(rename = "peer.address")]
+    pub peer_address: Option<String>,
 }
 
 #[derive(Debug)]
-struct SpanDependency<'a> {
-    pub span: &'a Trace,
-    pub upper: RefCell<HashMap<String, u32>>,
-    pub lower: RefCell<HashMap<String, u32>>,
+struct DependingInfo<'a> {
+    pub endpoint: &'a EndpointInfo,
+    pub metrics: Option<EndpointDependencyMetrics>,
+    pub last_seen: u64,
+}
+
+#[test]
+fn test_non_http_server_spans() {
+    use crate::data::fixtures::{server, URL_A, URL_B, URL_C};
+
+    // A -> gRPC server in B -> C, the gRPC span has no HTTP method
+    let mut grpc = server("2", Some("1"), URL_B, 10, 50);
+    grpc.tags.http_method = String::new();
+    let traces = vec![vec![
+        server("1", None, URL_A, 0, 100),
+        grpc,
+        server("3", Some("2"), URL_C, 20, 30),
+    ]];
+
+    let realtime = Trace::combine_to_realtime_data(&traces, vec![], &[], &Default::default());
+    assert_eq!(realtime.len(), 2);
+
+    let dependencies = Trace::to_endpoint_dependencies(&traces, &UrlMatcher::new());
+    assert_eq!(dependencies.len(), 2);
+    let c = dependencies
+        .iter()
+        .find(|d| d.endpoint.service == "svc-c")
+        .unwrap();
+    assert_eq!(c.depending_by[0].endpoint.service, "svc-a");
 }
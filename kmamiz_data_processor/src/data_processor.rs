@@ -2,19 +2,29 @@ use actix_web::web::Data;
 use http_client::{kubernetes::KubernetesClient, zipkin::ZipkinClient};
 use log::debug;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error,
     sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
     data::{
         combined_realtime_data::CombinedRealtimeData,
         connection_package::{RequestPackage, ResponsePackage},
+        coupling::CouplingMetrics,
+        critical_path::EndpointCriticalPath,
+        datastore_dependency::DatastoreDependency,
+        dependency_cycle::DependencyCycles,
+        endpoint_data_type::EndpointDataType,
         endpoint_dependency::EndpointDependency,
         envoy_log::EnvoyLog,
+        messaging_dependency::MessagingDependency,
         realtime_data::RealtimeData,
+        risk,
+        schema_change::EndpointSchemaChange,
+        service_dependency::ServiceDependency,
+        span_tree::SpanTree,
         trace::Trace,
     },
     http_client::{self, url_matcher::UrlMatcher},
@@ -24,45 +34,127 @@ pub struct DataProcessorState {
     pub url_matcher: Arc<UrlMatcher>,
     pub zipkin: Arc<ZipkinClient>,
     pub kubernetes: Arc<KubernetesClient>,
-    pub processed: Arc<Mutex<HashMap<String, i128>>>,
+    pub processed: Arc<Mutex<HashMap<String, ProcessedTrace>>>,
+    // IDs of traces deferred by the previous request, fetched again by the next one
+    pub deferred: Arc<Mutex<HashSet<String>>>,
+    pub sampling_rate: f64,
+    // requests are processed one at a time, in the order they arrive
+    pub queue: Arc<tokio::sync::Mutex<()>>,
+    pub responses: Arc<Mutex<ResponseCache>>,
 }
 
+/// Responses by unique ID, so that a retried request gets the same response instead of an empty one.
+#[derive(Debug)]
+pub struct ResponseCache {
+    ttl: Duration,
+    responses: HashMap<String, (Instant, ResponsePackage)>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            responses: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, unique_id: &str) -> Option<ResponsePackage> {
+        let ttl = self.ttl;
+        self.responses
+            .retain(|_, (created, _)| created.elapsed() < ttl);
+        self.responses.get(unique_id).map(|(_, r)| r.clone())
+    }
+
+    fn insert(&mut self, response: ResponsePackage) {
+        self.responses
+            .insert(response.unique_id.clone(), (Instant::now(), response));
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessedTrace {
+    // latest span timestamp in milliseconds, used for cleaning up
+    timestamp: i128,
+    // "{span_id}\t{kind}", CLIENT and SERVER spans might share an ID
+    spans: HashSet<String>,
+}
+
+/// Traces with a span ending within `settling` milliseconds before `time` are deferred to the next round.
+/// Spans arriving for an already processed trace are marked, so only their own data is counted.
+/// The processed traces are only read, see `mark_processed`. Returns the IDs of the deferred traces.
 fn filter_traces(
     traces: Vec<Vec<Trace>>,
-    processed: Arc<Mutex<HashMap<String, i128>>>,
-) -> (Vec<Vec<Trace>>, usize, usize) {
-    let mut processed = processed.lock().unwrap();
+    processed: &HashMap<String, ProcessedTrace>,
+    time: u64,
+    settling: u64,
+) -> (Vec<Vec<Trace>>, HashSet<String>, usize, usize) {
     let ori_len = traces.len();
+    let settled_before = (time.saturating_sub(settling) * 1000) as i128;
 
+    let mut deferred = HashSet::new();
+    let mut late = 0;
     let traces = traces
         .into_iter()
-        .filter(|t| {
-            let to_process = !t.is_empty() && !processed.contains_key(&t[0].trace_id);
-            if to_process {
-                processed.insert(t[0].trace_id.clone(), t[0].timestamp as i128 / 1000);
-            };
-            to_process
+        .filter_map(|mut t| {
+            let latest_end = t.iter().map(|s| (s.timestamp + s.duration) as i128).max()?;
+            if settling > 0 && latest_end > settled_before {
+                deferred.insert(t[0].trace_id.clone());
+                return None;
+            }
+
+            let entry = processed.get(&t[0].trace_id);
+            let is_late = entry.is_some_and(|e| !e.spans.is_empty());
+            let mut has_new = false;
+            for span in t.iter_mut() {
+                span.processed = entry.is_some_and(|e| e.spans.contains(&span_key(span)));
+                has_new |= !span.processed;
+            }
+            if has_new && is_late {
+                late += 1;
+            }
+            has_new.then_some(t)
         })
         .collect::<Vec<Vec<Trace>>>();
 
     let new_len = traces.len();
     debug!(
-        "Traces: [Total: {}] [New: {}] [Filtered: {}]",
+        "Traces: [Total: {}] [New: {}] [With late spans: {}] [Deferred: {}] [Filtered: {}]",
         ori_len,
-        new_len,
+        new_len - late,
+        late,
+        deferred.len(),
         ori_len - new_len
     );
-    (traces, ori_len, new_len)
+    (traces, deferred, ori_len, new_len)
+}
+
+fn span_key(span: &Trace) -> String {
+    format!("{}\t{}", span.id, span.kind)
+}
+
+/// Records the spans of the filtered traces, only once their response is built,
+/// so that a failed request can be retried with the same traces.
+fn mark_processed(processed: &mut HashMap<String, ProcessedTrace>, traces: &[Vec<Trace>]) {
+    for trace in traces.iter() {
+        let Some(first) = trace.first() else {
+            continue;
+        };
+        let entry = processed.entry(first.trace_id.clone()).or_default();
+        for span in trace.iter().filter(|s| !s.processed) {
+            entry.spans.insert(span_key(span));
+            let end = (span.timestamp + span.duration) as i128 / 1000;
+            entry.timestamp = entry.timestamp.max(end);
+        }
+    }
 }
 
-fn clean_up_traces(processed: Arc<Mutex<HashMap<String, i128>>>, timeout: i128) {
-    let mut processed = processed.lock().unwrap();
+fn clean_up_traces(processed: &mut HashMap<String, ProcessedTrace>, timeout: i128) {
     let now = SystemTime::now();
     let current = now.duration_since(UNIX_EPOCH).unwrap().as_millis() as i128;
 
     let mut to_remove = vec![];
-    for (k, ts) in processed.iter() {
-        if current - ts > timeout {
+    for (k, trace) in processed.iter() {
+        if current - trace.timestamp > timeout {
             to_remove.push(k.clone());
         }
     }
@@ -72,7 +164,28 @@ fn clean_up_traces(processed: Arc<Mutex<HashMap<String, i128>>>, timeout: i128)
     debug!("Timeout traces: {}", to_remove.len());
 }
 
-pub async fn collect_data(
+/// Overlapping requests would otherwise race on the processed traces and split the data between them.
+pub async fn process_request(
+    request: RequestPackage,
+    state: Data<DataProcessorState>,
+) -> Result<ResponsePackage, Box<dyn Error>> {
+    let queue = state.queue.clone();
+    let _turn = queue.lock().await;
+    if let Some(response) = state.responses.lock().unwrap().get(&request.unique_id) {
+        debug!(
+            "Request ID: {}, returning cached response",
+            request.unique_id
+        );
+        return Ok(response);
+    }
+
+    let responses = state.responses.clone();
+    let response = collect_data(request, state).await?;
+    responses.lock().unwrap().insert(response.clone());
+    Ok(response)
+}
+
+async fn collect_data(
     request: RequestPackage,
     state: Data<DataProcessorState>,
 ) -> Result<ResponsePackage, Box<dyn Error>> {
@@ -80,8 +193,34 @@ pub async fn collect_data(
     let zipkin = state.zipkin.clone();
     let kubernetes = state.kubernetes.clone();
 
-    let traces = zipkin.get_traces(request.look_back, request.time).await?;
-    let (traces, total_traces, processed_traces) = filter_traces(traces, state.processed.clone());
+    let mut traces = zipkin.get_traces(request.look_back, request.time).await?;
+    // deferred traces are fetched by ID, lookBack might not reach back to them
+    let fetched = traces
+        .iter()
+        .filter_map(|t| t.first())
+        .map(|s| s.trace_id.clone())
+        .collect::<HashSet<_>>();
+    let missing = state
+        .deferred
+        .lock()
+        .unwrap()
+        .difference(&fetched)
+        .cloned()
+        .collect::<Vec<_>>();
+    traces.extend(zipkin.get_traces_by_ids(&missing).await?);
+    let (traces, deferred, total_traces, processed_traces) = filter_traces(
+        traces,
+        &state.processed.lock().unwrap(),
+        request.time,
+        request.settling_delay.unwrap_or_default(),
+    );
+
+    // spans whose parent is missing, their chains are cut short
+    let orphans = SpanTree::from_traces(&traces)
+        .iter()
+        .map(|t| t.orphans.len())
+        .sum::<usize>();
+    debug!("Orphan spans: {orphans}");
 
     let namespaces = Trace::extract_namespaces(&traces);
 
@@ -95,32 +234,273 @@ pub async fn collect_data(
     }
 
     let s_logs = EnvoyLog::combine_logs(logs);
-    let rl_data = Trace::combine_to_realtime_data(&traces, s_logs, &replicas);
+    let mut sampling = request.sampling.unwrap_or_default();
+    sampling.default_rate.get_or_insert(state.sampling_rate);
+    let rl_data = Trace::combine_to_realtime_data(&traces, s_logs, &replicas, &sampling);
     let dependencies = Trace::to_endpoint_dependencies(&traces, &url_matcher);
-    let dependencies = if let Some(existing) = request.existing_dep {
-        EndpointDependency::combine(dependencies, existing)
+    let critical_paths = EndpointCriticalPath::from_traces(&traces, &url_matcher);
+    // combining also merges the per-span dependencies and their edge metrics
+    let dependencies =
+        EndpointDependency::combine(dependencies, request.existing_dep.unwrap_or_default());
+    let dependencies = if let Some(retention) = request.dependency_retention {
+        EndpointDependency::expire(dependencies, request.time * 1000, retention * 1000)
     } else {
         dependencies
     };
 
-    let combined = RealtimeData::combine(rl_data);
-    let datatype = CombinedRealtimeData::extract_datatype(&combined);
+    let service_dependencies = ServiceDependency::from_endpoint_dependencies(&dependencies);
+    let messaging_dependencies = MessagingDependency::from_traces(&traces, request.look_back);
+    let datastore_dependencies = DatastoreDependency::from_traces(&traces, &url_matcher);
+    let cycles = DependencyCycles::detect(&dependencies, &traces, &url_matcher);
+    let coupling = CouplingMetrics::from_endpoint_dependencies(&dependencies);
+
+    let combined = RealtimeData::combine(rl_data, request.look_back);
+    let mut datatype = CombinedRealtimeData::extract_datatype(&combined);
+    let existing_datatype = request.existing_datatype.unwrap_or_default();
+    let schema_changes = EndpointSchemaChange::detect(&existing_datatype, &datatype);
+    EndpointDataType::accumulate_statistics(&mut datatype, &existing_datatype);
+    let risks = risk::realtime_risk(&combined, &dependencies, &replicas);
 
-    clean_up_traces(state.processed.clone(), request.look_back as i128);
+    // requests are queued, the processed traces are not changed while this one is running
+    let mut processed = state.processed.lock().unwrap();
+    mark_processed(&mut processed, &traces);
+    clean_up_traces(&mut processed, request.look_back as i128);
+    drop(processed);
+    *state.deferred.lock().unwrap() = deferred;
 
     debug!("Request ID: {}", request.unique_id);
     debug!("Looking back {} from {}", request.look_back, request.time);
     debug!(
-        "Done data processing, with combined data: {}, dependencies: {}, datatype: {}",
+        "Done data processing, with combined data: {}, dependencies: {}, datatype: {}, critical paths: {}",
         combined.len(),
         dependencies.len(),
         datatype.len(),
+        critical_paths.len(),
     );
     Ok(ResponsePackage {
         unique_id: request.unique_id,
         combined,
         dependencies,
+        service_dependencies,
+        messaging_dependencies,
+        datastore_dependencies,
+        cycles,
+        coupling,
+        risks,
         datatype,
-        log: format!("Got {total_traces} traces, {processed_traces} new to process"),
+        schema_changes,
+        critical_paths,
+        log: format!(
+            "Got {total_traces} traces, {processed_traces} new to process, {orphans} orphan spans"
+        ),
     })
 }
+
+#[test]
+fn test_filter_traces() {
+    use crate::data::fixtures::{server, URL_A, URL_B};
+    let mut processed = HashMap::new();
+    let root = server("1", None, URL_A, 0, 100_000);
+    let late = server("2", Some("1"), URL_B, 10_000, 10_000);
+
+    // ends at 100ms, not settled at 1000ms with a 950ms delay
+    let (traces, deferred, _, new) = filter_traces(vec![vec![root.clone()]], &processed, 1000, 950);
+    assert_eq!((traces.len(), new), (0, 0));
+    assert!(deferred.contains("trace"));
+
+    let (traces, _, _, _) = filter_traces(vec![vec![root.clone()]], &processed, 1000, 500);
+    assert_eq!(traces.len(), 1);
+    assert!(!traces[0][0].processed);
+    // not marked until the response is built
+    assert!(processed.is_empty());
+    mark_processed(&mut processed, &traces);
+    assert_eq!(processed["trace"].timestamp, 100);
+
+    // the same spans again are filtered out, a late span comes with its processed trace
+    let (traces, _, _, _) = filter_traces(vec![vec![root.clone()]], &processed, 2000, 500);
+    assert!(traces.is_empty());
+    let (traces, _, _, _) = filter_traces(vec![vec![root, late]], &processed, 2000, 500);
+    assert_eq!(traces.len(), 1);
+    assert!(traces[0][0].processed);
+    assert!(!traces[0][1].processed);
+
+    // only the late span and its edge to the processed parent are counted
+    let url_matcher = UrlMatcher::new();
+    let dependencies = Trace::to_endpoint_dependencies(&traces, &url_matcher);
+    assert_eq!(dependencies.len(), 2);
+    assert!(dependencies
+        .iter()
+        .all(|d| d.depending_by.len() + d.depending_on.len() == 1));
+    let rl_data = Trace::combine_to_realtime_data(&traces, vec![], &[], &Default::default());
+    assert_eq!(rl_data.len(), 1);
+    assert_eq!(rl_data[0].service, "svc-b");
+    assert!(EndpointCriticalPath::from_traces(&traces, &url_matcher).is_empty());
+}
+
+#[test]
+fn test_response_cache() {
+    let response = |unique_id: &str| ResponsePackage {
+        unique_id: unique_id.to_owned(),
+        combined: vec![],
+        dependencies: vec![],
+        service_dependencies: vec![],
+        messaging_dependencies: vec![],
+        datastore_dependencies: vec![],
+        cycles: DependencyCycles::detect(&[], &[], &UrlMatcher::new()),
+        coupling: CouplingMetrics::from_endpoint_dependencies(&[]),
+        risks: vec![],
+        datatype: vec![],
+        schema_changes: vec![],
+        critical_paths: vec![],
+        log: unique_id.to_owned(),
+    };
+
+    let mut cache = ResponseCache::new(Duration::from_secs(60));
+    cache.insert(response("a"));
+    assert_eq!(cache.get("a").map(|r| r.log), Some("a".to_owned()));
+    assert!(cache.get("b").is_none());
+
+    let mut expired = ResponseCache::new(Duration::ZERO);
+    expired.insert(response("a"));
+    assert!(expired.get("a").is_none());
+    assert!(expired.responses.is_empty());
+}
+
+/// Serves both Zipkin and the Kubernetes API, `respond` gets the request and closes the
+/// connection without a response on None.
+#[cfg(test)]
+async fn mock_server(respond: impl Fn(&str) -> Option<String> + Send + 'static) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let Some(body) = respond(&String::from_utf8_lossy(&buf[..n])) else {
+                continue;
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    host
+}
+
+#[cfg(test)]
+fn mock_state(host: String) -> Data<DataProcessorState> {
+    use crate::env::Env;
+
+    let env = Arc::new(Env {
+        bind_ip: "127.0.0.1".to_owned(),
+        port: 0,
+        zipkin_url: host.clone(),
+        is_k8s: false,
+        kube_api_host: host,
+        sampling_rate: 100.0,
+        response_ttl: 60,
+    });
+    Data::new(DataProcessorState {
+        url_matcher: Arc::new(UrlMatcher::new()),
+        zipkin: Arc::new(ZipkinClient::new(env.clone())),
+        kubernetes: Arc::new(KubernetesClient::new(env)),
+        processed: Arc::new(Mutex::new(HashMap::new())),
+        deferred: Arc::new(Mutex::new(HashSet::new())),
+        sampling_rate: 100.0,
+        queue: Arc::new(tokio::sync::Mutex::new(())),
+        responses: Arc::new(Mutex::new(ResponseCache::new(Duration::from_secs(60)))),
+    })
+}
+
+#[cfg(test)]
+fn mock_request(unique_id: &str, time: u64, settling_delay: Option<u64>) -> RequestPackage {
+    RequestPackage {
+        unique_id: unique_id.to_owned(),
+        look_back: 60_000,
+        time,
+        existing_dep: None,
+        existing_datatype: None,
+        dependency_retention: None,
+        settling_delay,
+        sampling: None,
+    }
+}
+
+#[tokio::test]
+async fn test_retry_after_failed_request() {
+    use crate::data::fixtures::{server, URL_A, URL_B};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let traces = serde_json::to_string(&vec![vec![
+        server("1", None, URL_A, 0, 100),
+        server("2", Some("1"), URL_B, 10, 50),
+    ]])
+    .unwrap();
+    // the first Kubernetes request is dropped
+    let kubernetes_failed = AtomicBool::new(false);
+    let host = mock_server(move |request| {
+        if request.contains("/zipkin/") {
+            Some(traces.clone())
+        } else if !kubernetes_failed.swap(true, Ordering::Relaxed) {
+            None
+        } else {
+            Some("{}".to_owned())
+        }
+    })
+    .await;
+    let state = mock_state(host);
+    let request = mock_request("retried", 60_000, None);
+
+    assert!(process_request(request.clone(), state.clone())
+        .await
+        .is_err());
+    assert!(state.processed.lock().unwrap().is_empty());
+    let response = process_request(request, state).await.unwrap();
+    assert!(response.log.starts_with("Got 1 traces, 1 new to process"));
+    assert_eq!(response.dependencies.len(), 2);
+}
+
+#[tokio::test]
+async fn test_deferred_traces_are_fetched_again() {
+    use crate::data::fixtures::{server, URL_A, URL_B};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let traces = serde_json::to_string(&vec![vec![
+        server("1", None, URL_A, 0, 100),
+        server("2", Some("1"), URL_B, 10, 50),
+    ]])
+    .unwrap();
+    // the trace is only in the window of the first request
+    let listed = AtomicBool::new(false);
+    let host = mock_server(move |request| {
+        let by_id = request.contains("/zipkin/api/v2/traceMany?traceIds=trace ");
+        let listing = request.contains("/zipkin/api/v2/traces?");
+        if by_id || (listing && !listed.swap(true, Ordering::Relaxed)) {
+            Some(traces.clone())
+        } else if request.contains("/zipkin/") {
+            Some("[]".to_owned())
+        } else {
+            Some("{}".to_owned())
+        }
+    })
+    .await;
+    let state = mock_state(host);
+
+    let response = process_request(mock_request("first", 60_000, Some(60_000)), state.clone())
+        .await
+        .unwrap();
+    assert!(response.log.starts_with("Got 1 traces, 0 new to process"));
+    assert!(state.deferred.lock().unwrap().contains("trace"));
+
+    let response = process_request(mock_request("second", 120_000, Some(60_000)), state.clone())
+        .await
+        .unwrap();
+    assert!(response.log.starts_with("Got 1 traces, 1 new to process"));
+    assert_eq!(response.dependencies.len(), 2);
+    assert!(state.deferred.lock().unwrap().is_empty());
+}
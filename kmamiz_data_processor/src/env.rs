@@ -9,6 +9,10 @@ pub struct Env {
     pub zipkin_url: String,
     pub is_k8s: bool,
     pub kube_api_host: String,
+    // percent of requests traced, used when a request does not specify a sampling rate
+    pub sampling_rate: f64,
+    // in seconds, how long responses are kept for retried requests
+    pub response_ttl: u64,
 }
 
 impl Env {
@@ -27,12 +31,22 @@ impl Env {
             format!("https://{}:{}", k8s_api_host, k8s_api_port)
         };
 
+        let sampling_rate = env::var("SAMPLING_RATE")
+            .map(|r| r.parse().expect("failed to parse SAMPLING_RATE"))
+            .unwrap_or(100.0);
+
+        let response_ttl = env::var("RESPONSE_TTL")
+            .map(|t| t.parse().expect("failed to parse RESPONSE_TTL"))
+            .unwrap_or(600);
+
         Env {
             bind_ip,
             port,
             zipkin_url: Env::read_env("ZIPKIN_URL"),
             is_k8s,
             kube_api_host,
+            sampling_rate,
+            response_ttl,
         }
     }
 
@@ -0,0 +1,398 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::json;
+
+use crate::data::endpoint_dependency::EndpointDependency;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Dot,
+    Mermaid,
+    GraphMl,
+    Cytoscape,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ExportGranularity {
+    Endpoint,
+    Service,
+}
+
+#[derive(Debug)]
+pub struct ExportParseError(String);
+impl Error for ExportParseError {}
+impl Display for ExportParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error parsing export option: {}", self.0)
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExportParseError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "dot" | "graphviz" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            "graphml" => Ok(Self::GraphMl),
+            "cytoscape" => Ok(Self::Cytoscape),
+            _ => Err(ExportParseError(input.to_owned())),
+        }
+    }
+}
+
+impl FromStr for ExportGranularity {
+    type Err = ExportParseError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "endpoint" => Ok(Self::Endpoint),
+            "service" => Ok(Self::Service),
+            _ => Err(ExportParseError(input.to_owned())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportGranularity {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub granularity: ExportGranularity,
+    // only keep nodes in these namespaces, edges need both ends kept
+    pub namespaces: Option<Vec<String>>,
+    #[serde(default)]
+    pub collapse_versions: bool,
+    // label edges with observed call counts
+    #[serde(default)]
+    pub edge_labels: bool,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Dot => "text/vnd.graphviz",
+            ExportFormat::Mermaid => "text/plain",
+            ExportFormat::GraphMl => "application/xml",
+            ExportFormat::Cytoscape => "application/json",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct GraphNode {
+    label: String,
+    namespace: String,
+    service: String,
+}
+
+#[derive(Debug, Default)]
+struct Graph {
+    nodes: BTreeMap<String, GraphNode>,
+    // (from, to) -> calls
+    edges: BTreeMap<(String, String), u64>,
+}
+
+pub fn export(dependencies: &[EndpointDependency], options: &ExportOptions) -> String {
+    let graph = to_graph(dependencies, options);
+    match options.format {
+        ExportFormat::Dot => to_dot(&graph, options),
+        ExportFormat::Mermaid => to_mermaid(&graph, options),
+        ExportFormat::GraphMl => to_graphml(&graph),
+        ExportFormat::Cytoscape => to_cytoscape(&graph, options),
+    }
+}
+
+fn to_graph(dependencies: &[EndpointDependency], options: &ExportOptions) -> Graph {
+    let in_namespace = |namespace: &String| {
+        options
+            .namespaces
+            .as_ref()
+            .map(|n| n.contains(namespace))
+            .unwrap_or(true)
+    };
+    let mut graph = Graph::default();
+
+    for dep in dependencies.iter() {
+        if !in_namespace(&dep.endpoint.namespace) {
+            continue;
+        }
+        let from = add_node(&mut graph, &dep.endpoint, options);
+        for item in dep.depending_on.iter().filter(|d| d.distance == 1) {
+            if !in_namespace(&item.endpoint.namespace) {
+                continue;
+            }
+            let to = add_node(&mut graph, &item.endpoint, options);
+            let calls = item.metrics.as_ref().map(|m| m.calls).unwrap_or_default();
+            *graph.edges.entry((from.clone(), to)).or_insert(0) += calls;
+        }
+    }
+    graph
+}
+
+fn add_node(
+    graph: &mut Graph,
+    endpoint: &crate::data::endpoint_info::EndpointInfo,
+    options: &ExportOptions,
+) -> String {
+    let version = if options.collapse_versions {
+        "*"
+    } else {
+        endpoint.version.as_str()
+    };
+    let service_id = format!("{}\t{}\t{version}", endpoint.service, endpoint.namespace);
+    let service_label = if options.collapse_versions {
+        format!("{}.{}", endpoint.service, endpoint.namespace)
+    } else {
+        format!(
+            "{}.{} ({})",
+            endpoint.service, endpoint.namespace, endpoint.version
+        )
+    };
+
+    let (id, label) = match options.granularity {
+        ExportGranularity::Service => (service_id, service_label),
+        ExportGranularity::Endpoint => {
+            let method = serde_json::to_value(&endpoint.method)
+                .ok()
+                .and_then(|m| m.as_str().map(|m| m.to_uppercase()))
+                .unwrap_or_default();
+            (
+                format!("{service_id}\t{method}\t{}", endpoint.path),
+                format!("{service_label}\n{method} {}", endpoint.path),
+            )
+        }
+    };
+    graph.nodes.entry(id.clone()).or_insert_with(|| GraphNode {
+        label,
+        namespace: endpoint.namespace.clone(),
+        service: endpoint.service.clone(),
+    });
+    id
+}
+
+/// Node IDs are replaced by their index, unique names contain tabs and slashes.
+fn node_ids(graph: &Graph) -> BTreeMap<&str, String> {
+    graph
+        .nodes
+        .keys()
+        .enumerate()
+        .map(|(i, id)| (id.as_str(), format!("n{i}")))
+        .collect()
+}
+
+fn to_dot(graph: &Graph, options: &ExportOptions) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let ids = node_ids(graph);
+    let mut lines = vec![
+        "digraph dependencies {".to_owned(),
+        "  rankdir=LR;".to_owned(),
+    ];
+    for (id, node) in graph.nodes.iter() {
+        lines.push(format!(
+            "  {} [label=\"{}\"];",
+            ids[id.as_str()],
+            escape(&node.label)
+        ));
+    }
+    for ((from, to), calls) in graph.edges.iter() {
+        let label = if options.edge_labels {
+            format!(" [label=\"{calls}\"]")
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "  {} -> {}{label};",
+            ids[from.as_str()],
+            ids[to.as_str()]
+        ));
+    }
+    lines.push("}".to_owned());
+    lines.join("\n")
+}
+
+fn to_mermaid(graph: &Graph, options: &ExportOptions) -> String {
+    let escape = |s: &str| s.replace('"', "#quot;").replace('\n', "<br/>");
+    let ids = node_ids(graph);
+    let mut lines = vec!["flowchart LR".to_owned()];
+    for (id, node) in graph.nodes.iter() {
+        lines.push(format!(
+            "  {}[\"{}\"]",
+            ids[id.as_str()],
+            escape(&node.label)
+        ));
+    }
+    for ((from, to), calls) in graph.edges.iter() {
+        let label = if options.edge_labels {
+            format!("|{calls}|")
+        } else {
+            String::new()
+        };
+        lines.push(format!(
+            "  {} -->{label} {}",
+            ids[from.as_str()],
+            ids[to.as_str()]
+        ));
+    }
+    lines.join("\n")
+}
+
+fn to_graphml(graph: &Graph) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let ids = node_ids(graph);
+    let mut lines = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_owned(),
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#.to_owned(),
+        r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#.to_owned(),
+        r#"  <key id="namespace" for="node" attr.name="namespace" attr.type="string"/>"#.to_owned(),
+        r#"  <key id="service" for="node" attr.name="service" attr.type="string"/>"#.to_owned(),
+        r#"  <key id="calls" for="edge" attr.name="calls" attr.type="long"/>"#.to_owned(),
+        r#"  <graph id="dependencies" edgedefault="directed">"#.to_owned(),
+    ];
+    for (id, node) in graph.nodes.iter() {
+        lines.push(format!("    <node id=\"{}\">", ids[id.as_str()]));
+        lines.push(format!(
+            "      <data key=\"label\">{}</data>",
+            escape(&node.label)
+        ));
+        lines.push(format!(
+            "      <data key=\"namespace\">{}</data>",
+            escape(&node.namespace)
+        ));
+        lines.push(format!(
+            "      <data key=\"service\">{}</data>",
+            escape(&node.service)
+        ));
+        lines.push("    </node>".to_owned());
+    }
+    for (i, ((from, to), calls)) in graph.edges.iter().enumerate() {
+        lines.push(format!(
+            "    <edge id=\"e{i}\" source=\"{}\" target=\"{}\">",
+            ids[from.as_str()],
+            ids[to.as_str()]
+        ));
+        lines.push(format!("      <data key=\"calls\">{calls}</data>"));
+        lines.push("    </edge>".to_owned());
+    }
+    lines.push("  </graph>".to_owned());
+    lines.push("</graphml>".to_owned());
+    lines.join("\n")
+}
+
+fn to_cytoscape(graph: &Graph, options: &ExportOptions) -> String {
+    let ids = node_ids(graph);
+    let nodes = graph
+        .nodes
+        .iter()
+        .map(|(id, node)| {
+            json!({"data": {
+                "id": ids[id.as_str()],
+                "name": id,
+                "label": node.label,
+                "namespace": node.namespace,
+                "service": node.service,
+            }})
+        })
+        .collect::<Vec<_>>();
+    let edges = graph
+        .edges
+        .iter()
+        .enumerate()
+        .map(|(i, ((from, to), calls))| {
+            let mut data = json!({
+                "id": format!("e{i}"),
+                "source": ids[from.as_str()],
+                "target": ids[to.as_str()],
+                "calls": calls,
+            });
+            if options.edge_labels {
+                data["label"] = json!(calls.to_string());
+            }
+            json!({ "data": data })
+        })
+        .collect::<Vec<_>>();
+    json!({"elements": {"nodes": nodes, "edges": edges}}).to_string()
+}
+
+#[test]
+fn test_export() {
+    use crate::data::fixtures::{dependencies, endpoint, server, URL_A, URL_B};
+    use crate::http_client::url_matcher::UrlMatcher;
+
+    let (a, b) = (endpoint(URL_A), endpoint(URL_B));
+    let other = endpoint("http://svc-c.other.svc.cluster.local/c");
+    let mut b_v2 = server("1", None, URL_B, 0, 10);
+    b_v2.tags.istio_canonical_revision = "v2".to_owned();
+    let b_v2 = b_v2.to_endpoint_info(&UrlMatcher::new()).unwrap();
+    // A -> B -> C in another namespace, A -> B v2
+    let dependencies = dependencies(&[(&a, &b, 1), (&b, &other, 1), (&a, &b_v2, 1)]);
+
+    let mut options = ExportOptions {
+        format: ExportFormat::Dot,
+        granularity: ExportGranularity::Service,
+        namespaces: Some(vec!["ns".to_owned()]),
+        collapse_versions: true,
+        edge_labels: true,
+    };
+    assert_eq!(
+        export(&dependencies, &options),
+        r#"digraph dependencies {
+  rankdir=LR;
+  n0 [label="svc-a.ns"];
+  n1 [label="svc-b.ns"];
+  n0 -> n1 [label="2"];
+}"#
+    );
+
+    options.format = ExportFormat::Mermaid;
+    options.collapse_versions = false;
+    assert_eq!(
+        export(&dependencies, &options),
+        r#"flowchart LR
+  n0["svc-a.ns (latest)"]
+  n1["svc-b.ns (latest)"]
+  n2["svc-b.ns (v2)"]
+  n0 -->|1| n1
+  n0 -->|1| n2"#
+    );
+
+    options.format = ExportFormat::Cytoscape;
+    options.granularity = ExportGranularity::Endpoint;
+    options.namespaces = None;
+    let cytoscape: serde_json::Value =
+        serde_json::from_str(&export(&dependencies, &options)).unwrap();
+    assert_eq!(cytoscape["elements"]["nodes"].as_array().unwrap().len(), 4);
+    assert_eq!(cytoscape["elements"]["edges"].as_array().unwrap().len(), 3);
+
+    options.format = ExportFormat::GraphMl;
+    let graphml = export(&dependencies, &options);
+    assert!(graphml.contains(r#"<edge id="e2" source="n1" target="n3">"#));
+    assert!(graphml.ends_with("</graphml>"));
+}
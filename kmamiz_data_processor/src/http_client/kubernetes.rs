@@ -115,7 +115,7 @@ impl KubernetesClient {
             .get_str(&url)
             .await?
             .split('\n')
-            .filter(|l| (l.contains("script log: ") || l.contains("wasm log ")))
+            .filter(|l| l.contains("script log: ") || l.contains("wasm log "))
             .filter_map(|l| {
                 let replaced = re.replace(l, "\t").to_string();
                 let replaced = re_post.replace(&replaced, "").to_string();
@@ -8,6 +8,8 @@ use reqwest::{
 use crate::{data::trace::Trace, env::Env};
 
 static SERVICE_NAME: &str = "istio-ingressgateway.istio-system";
+// trace IDs per request, keeping the URL short
+const TRACE_IDS_PER_REQUEST: usize = 100;
 #[derive(Debug)]
 pub struct ZipkinClient {
     client: Client,
@@ -40,4 +42,22 @@ impl ZipkinClient {
 
         Ok(self.client.get(url).send().await?.json().await?)
     }
+
+    /// Traces by ID, wherever they are in time. Unknown IDs are left out.
+    pub async fn get_traces_by_ids(
+        &self,
+        trace_ids: &[String],
+    ) -> Result<Vec<Vec<Trace>>, Box<dyn Error>> {
+        let mut traces = vec![];
+        for ids in trace_ids.chunks(TRACE_IDS_PER_REQUEST) {
+            let url = format!(
+                "{}/zipkin/api/v2/traceMany?traceIds={}",
+                self.zipkin_url,
+                ids.join(",")
+            );
+            let chunk: Vec<Vec<Trace>> = self.client.get(url).send().await?.json().await?;
+            traces.extend(chunk);
+        }
+        Ok(traces)
+    }
 }
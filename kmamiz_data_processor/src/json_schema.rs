@@ -0,0 +1,279 @@
+use serde_json::{json, Map, Value};
+
+use crate::json_utils::{self, ObjectSchema, Property, Schema, StringFormat, UniqueNames};
+
+static DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Nested object types, identical structures share one definition.
+type Definitions = UniqueNames<Value>;
+
+/// JSON Schema (draft 2020-12) of the inferred schema, nested objects are placed in `$defs`
+/// under the same names as the TypeScript types of `json_utils::to_types`.
+pub fn to_json_schema(schema: &Schema) -> Value {
+    let mut defs = Definitions::default();
+    // the root object is the schema itself
+    defs.reserve("Root");
+    let root = match &schema.object {
+        Some(obj) if schema.is_only_object() => object_schema("", obj, &mut defs),
+        None if schema.is_empty() => object_schema("", &ObjectSchema::default(), &mut defs),
+        _ if schema.is_only_array() => schema_of("", "ArrayItem", schema, &mut defs),
+        _ => schema_of("", "RootObject", schema, &mut defs),
+    };
+
+    let mut root = match root {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    root.insert("$schema".to_owned(), json!(DRAFT));
+    if !defs.defs.is_empty() {
+        root.insert("$defs".to_owned(), json!(defs.defs));
+    }
+    Value::Object(root)
+}
+
+/// `path` and `name` as in `json_utils::to_types`.
+fn schema_of(path: &str, name: &str, schema: &Schema, defs: &mut Definitions) -> Value {
+    let mut types = vec![];
+    for (present, ty) in [
+        (schema.string, "string"),
+        (schema.number, "number"),
+        (schema.boolean, "boolean"),
+        (schema.null, "null"),
+    ] {
+        if present {
+            types.push(ty);
+        }
+    }
+
+    let mut variants = vec![];
+    if let Some(obj) = &schema.object {
+        let def = object_schema(path, obj, defs);
+        let name = if name.is_empty() { "Item" } else { name };
+        let def_name = defs.add(name, def.to_string(), def);
+        variants.push(json!({ "$ref": format!("#/$defs/{def_name}") }));
+    }
+    if let Some(items) = &schema.array {
+        let items = schema_of(path, name, items, defs);
+        variants.push(json!({ "type": "array", "items": items }));
+    }
+
+    let mut primitives = match types.as_slice() {
+        [] => None,
+        [ty] => Some(json!({ "type": ty })),
+        _ => Some(json!({ "type": types })),
+    };
+    if let Some(primitives) = primitives.as_mut().filter(|_| schema.string) {
+        string_keywords(schema, primitives);
+    }
+    match (primitives, variants.len()) {
+        (None, 0) => json!({}),
+        (None, 1) => variants.remove(0),
+        (Some(primitives), 0) => primitives,
+        (primitives, _) => {
+            variants.extend(primitives);
+            json!({ "anyOf": variants })
+        }
+    }
+}
+
+/// `format` and `enum` of the strings, enums only when there are no other primitives.
+fn string_keywords(schema: &Schema, primitives: &mut Value) {
+    match schema.format {
+        Some(StringFormat::Base64) => primitives["contentEncoding"] = json!("base64"),
+        Some(format) => primitives["format"] = json!(format.name()),
+        None => {}
+    }
+    let Some(values) = schema.enum_values() else {
+        return;
+    };
+    if !schema.number && !schema.boolean {
+        let mut values = values.iter().map(|v| json!(v)).collect::<Vec<_>>();
+        if schema.null {
+            values.push(Value::Null);
+        }
+        primitives["enum"] = json!(values);
+    }
+}
+
+fn object_schema(path: &str, obj: &ObjectSchema, defs: &mut Definitions) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for (name, property) in obj.properties.iter() {
+        if property.schema.is_only_null() {
+            // nothing to tell the type from, `?: unknown` in TypeScript
+            properties.insert(name.clone(), json!({}));
+            continue;
+        }
+        let child = json_utils::type_path(path, name);
+        properties.insert(
+            name.clone(),
+            schema_of(&child, &child, &property.schema, defs),
+        );
+        if !property.is_optional(obj) {
+            required.push(name.clone());
+        }
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// Schema of a stored JSON Schema made by `to_json_schema`, with the types and fields but not
+/// the formats or enum values. Every object counts as one sample, so merging the schemas of
+/// several windows keeps the fields missing from any of them optional.
+pub fn from_json_schema(json_schema: &Value) -> Schema {
+    schema_from(json_schema, json_schema, 0)
+}
+
+fn schema_from(node: &Value, root: &Value, depth: usize) -> Schema {
+    let mut schema = Schema::default();
+    // references are never cyclic in inferred schemas, the limit guards against stored ones
+    if depth > 64 {
+        return schema;
+    }
+    if let Some(name) = node["$ref"]
+        .as_str()
+        .and_then(|r| r.strip_prefix("#/$defs/"))
+    {
+        schema.merge(&schema_from(&root["$defs"][name], root, depth + 1));
+    }
+    for variant in node["anyOf"].as_array().into_iter().flatten() {
+        schema.merge(&schema_from(variant, root, depth + 1));
+    }
+    let types = match &node["type"] {
+        Value::String(ty) => vec![ty.as_str()],
+        Value::Array(types) => types.iter().filter_map(|t| t.as_str()).collect(),
+        _ => vec![],
+    };
+    for ty in types {
+        match ty {
+            "null" => schema.null = true,
+            "boolean" => schema.boolean = true,
+            "number" | "integer" => schema.number = true,
+            "string" => schema.string = true,
+            _ => {}
+        }
+    }
+    if node.get("items").is_some() {
+        let items = schema_from(&node["items"], root, depth + 1);
+        schema
+            .array
+            .get_or_insert_with(Default::default)
+            .merge(&items);
+    }
+    if let Some(properties) = node["properties"].as_object() {
+        let required = node["required"].as_array();
+        let object = ObjectSchema {
+            samples: 1,
+            properties: properties
+                .iter()
+                .map(|(name, property)| {
+                    let property = Property {
+                        schema: schema_from(property, root, depth + 1),
+                        occurrences: required.is_some_and(|r| r.iter().any(|n| n == name)) as usize,
+                    };
+                    (name.clone(), property)
+                })
+                .collect(),
+        };
+        schema.merge(&Schema {
+            object: Some(object),
+            ..Default::default()
+        });
+    }
+    schema
+}
+
+#[test]
+fn test_to_json_schema() {
+    let samples = [
+        json!({ "id": 1, "owner": { "name": "a" }, "tags": ["x"], "parent": null }),
+        json!({ "id": 2, "owner": { "name": "b" }, "tags": [], "parent": { "name": "c" } }),
+        json!({ "id": 3, "owner": { "name": "b" }, "tags": [1], "note": "n" }),
+    ];
+    let schema = to_json_schema(&Schema::infer_all(samples.iter()));
+    assert_eq!(
+        schema,
+        json!({
+            "$schema": DRAFT,
+            "type": "object",
+            "properties": {
+                "id": { "type": "number" },
+                "note": { "type": "string" },
+                "owner": { "$ref": "#/$defs/Owner" },
+                // same structure as owner, shares the definition
+                "parent": { "anyOf": [{ "$ref": "#/$defs/Owner" }, { "type": "null" }] },
+                "tags": { "type": "array", "items": { "type": ["string", "number"] } }
+            },
+            "required": ["id", "owner", "tags"],
+            "$defs": {
+                "Owner": {
+                    "type": "object",
+                    "properties": { "name": { "type": "string" } },
+                    "required": ["name"]
+                }
+            }
+        })
+    );
+
+    let schema = to_json_schema(&Schema::infer(
+        &json!([{ "a": { "b": 1 } }, { "a": { "c": true } }]),
+    ));
+    assert_eq!(schema["type"], "array");
+    assert_eq!(schema["items"]["$ref"], "#/$defs/ArrayItem");
+    assert_eq!(schema["$defs"]["A"]["required"], json!([]));
+
+    let samples = (0..15)
+        .map(|i| {
+            let state = [json!("on"), json!("off"), Value::Null][i % 3].clone();
+            json!({ "at": "2024-01-01T00:00:00Z", "state": state })
+        })
+        .collect::<Vec<_>>();
+    let schema = to_json_schema(&Schema::infer_all(samples.iter()));
+    assert_eq!(
+        schema["properties"]["at"],
+        json!({ "type": "string", "format": "date-time" })
+    );
+    assert_eq!(
+        schema["properties"]["state"],
+        json!({ "type": ["string", "null"], "enum": ["off", "on", null] })
+    );
+
+    // named like the TypeScript types, null-only fields are optional and unknown in both
+    let inferred = Schema::infer(&json!({
+        "user": { "home_address": { "city": "x" } },
+        "root": { "id": 1 },
+        "deleted": null
+    }));
+    let types = json_utils::to_types(&inferred);
+    assert!(types.contains("type UserHomeAddress = {"));
+    assert!(types.contains("type Root2 = {"));
+    assert!(types.contains("deleted?: unknown;"));
+    let schema = to_json_schema(&inferred);
+    assert_eq!(
+        schema["$defs"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>(),
+        ["Root2", "User", "UserHomeAddress"]
+    );
+    assert_eq!(schema["properties"]["deleted"], json!({}));
+    assert_eq!(schema["required"], json!(["root", "user"]));
+
+    // read back with the same types and optional fields
+    let inferred = Schema::infer_all(
+        [
+            json!({ "id": 1, "owner": { "name": "a" }, "tags": ["x", null] }),
+            json!({ "id": "2", "owner": { "name": "b" }, "tags": [] }),
+            json!({ "id": 3, "note": "n" }),
+        ]
+        .iter(),
+    );
+    let schema = from_json_schema(&to_json_schema(&inferred));
+    assert_eq!(
+        json_utils::to_types(&schema),
+        json_utils::to_types(&inferred)
+    );
+    let object = schema.object.as_ref().unwrap();
+    assert!(!object.properties["id"].is_optional(object));
+    assert!(object.properties["owner"].is_optional(object));
+}
@@ -1,7 +1,267 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::OnceLock,
+};
 
+use regex::Regex;
 use serde_json::{Map, Value};
 
+// enum candidates need at least this many strings, with at most half as many distinct values
+const MIN_ENUM_SAMPLES: usize = 10;
+const MAX_ENUM_VALUES: usize = 10;
+const MAX_ENUM_LENGTH: usize = 64;
+
+/// Inferred type of JSON values, merging keeps every type seen so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    pub null: bool,
+    pub boolean: bool,
+    pub number: bool,
+    pub string: bool,
+    // number of strings seen
+    pub strings: usize,
+    // format shared by every string
+    pub format: Option<StringFormat>,
+    // distinct strings, None once there are too many for an enum
+    pub values: Option<BTreeSet<String>>,
+    // union of the types of every element
+    pub array: Option<Box<Schema>>,
+    pub object: Option<ObjectSchema>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectSchema {
+    // number of objects merged
+    pub samples: usize,
+    pub properties: BTreeMap<String, Property>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Property {
+    pub schema: Schema,
+    // number of objects having the property
+    pub occurrences: usize,
+}
+
+impl Schema {
+    pub fn infer(value: &Value) -> Schema {
+        let mut schema = Schema::default();
+        match value {
+            Value::Null => schema.null = true,
+            Value::Bool(_) => schema.boolean = true,
+            Value::Number(_) => schema.number = true,
+            Value::String(s) => {
+                schema.string = true;
+                schema.strings = 1;
+                schema.format = StringFormat::detect(s);
+                if s.len() <= MAX_ENUM_LENGTH {
+                    schema.values = Some(BTreeSet::from([s.clone()]));
+                }
+            }
+            Value::Array(arr) => schema.array = Some(Box::new(Schema::infer_all(arr))),
+            Value::Object(obj) => {
+                schema.object = Some(ObjectSchema {
+                    samples: 1,
+                    properties: obj
+                        .iter()
+                        .map(|(k, v)| {
+                            let property = Property {
+                                schema: Schema::infer(v),
+                                occurrences: 1,
+                            };
+                            (k.clone(), property)
+                        })
+                        .collect(),
+                })
+            }
+        }
+        schema
+    }
+
+    pub fn infer_all<'a>(values: impl IntoIterator<Item = &'a Value>) -> Schema {
+        let mut schema = Schema::default();
+        for value in values {
+            schema.merge(&Schema::infer(value));
+        }
+        schema
+    }
+
+    pub fn merge(&mut self, other: &Schema) {
+        if other.string && !self.string {
+            self.format = other.format;
+            self.values = other.values.clone();
+        } else if other.string {
+            if self.format != other.format {
+                self.format = None;
+            }
+            self.values = match (self.values.take(), &other.values) {
+                (Some(mut values), Some(other)) => {
+                    values.extend(other.iter().cloned());
+                    Some(values).filter(|v| v.len() <= MAX_ENUM_VALUES)
+                }
+                _ => None,
+            };
+        }
+        self.strings += other.strings;
+        self.null |= other.null;
+        self.boolean |= other.boolean;
+        self.number |= other.number;
+        self.string |= other.string;
+        if let Some(items) = &other.array {
+            self.array.get_or_insert_with(Default::default).merge(items);
+        }
+        if let Some(other) = &other.object {
+            let object = self.object.get_or_insert_with(Default::default);
+            object.samples += other.samples;
+            for (name, property) in other.properties.iter() {
+                let entry = object.properties.entry(name.clone()).or_default();
+                entry.schema.merge(&property.schema);
+                entry.occurrences += property.occurrences;
+            }
+        }
+    }
+
+    /// Nothing observed, e.g. the elements of empty arrays.
+    pub fn is_empty(&self) -> bool {
+        *self == Schema::default()
+    }
+
+    pub fn is_only_null(&self) -> bool {
+        *self
+            == Schema {
+                null: true,
+                ..Default::default()
+            }
+    }
+
+    pub fn is_only_object(&self) -> bool {
+        self.object.is_some()
+            && Schema {
+                object: None,
+                ..self.clone()
+            }
+            .is_empty()
+    }
+
+    pub fn is_only_array(&self) -> bool {
+        self.array.is_some()
+            && Schema {
+                array: None,
+                ..self.clone()
+            }
+            .is_empty()
+    }
+
+    /// Few distinct strings seen many times, strings with a format are never enums.
+    pub fn enum_values(&self) -> Option<&BTreeSet<String>> {
+        self.values.as_ref().filter(|values| {
+            self.format.is_none()
+                && self.strings >= MIN_ENUM_SAMPLES
+                && values.len() * 2 <= self.strings
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringFormat {
+    DateTime,
+    Date,
+    Uuid,
+    Email,
+    Uri,
+    Ipv4,
+    Ipv6,
+    ObjectId,
+    Base64,
+}
+
+struct FormatMatchers {
+    date_time: Regex,
+    date: Regex,
+    uuid: Regex,
+    email: Regex,
+    uri: Regex,
+    object_id: Regex,
+    base64: Regex,
+}
+
+fn matchers() -> &'static FormatMatchers {
+    static MATCHERS: OnceLock<FormatMatchers> = OnceLock::new();
+    MATCHERS.get_or_init(|| FormatMatchers {
+        date_time: Regex::new(
+            r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])[Tt ]([01]\d|2[0-3]):[0-5]\d:([0-5]\d|60)(\.\d+)?([Zz]|[+-]([01]\d|2[0-3]):[0-5]\d)$",
+        )
+        .unwrap(),
+        date: Regex::new(r"^\d{4}-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])$").unwrap(),
+        uuid: Regex::new(r"^[0-9a-fA-F]{8}(-[0-9a-fA-F]{4}){3}-[0-9a-fA-F]{12}$").unwrap(),
+        email: Regex::new(r"^[^\s@]+@[^\s@]+\.[^\s@.]+$").unwrap(),
+        uri: Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*://[^\s/?#]+[^\s]*$").unwrap(),
+        object_id: Regex::new(r"^[0-9a-fA-F]{24}$").unwrap(),
+        base64: Regex::new(r"^(?:[A-Za-z0-9+/]{4})+(?:[A-Za-z0-9+/]{2}==|[A-Za-z0-9+/]{3}=)?$")
+            .unwrap(),
+    })
+}
+
+impl StringFormat {
+    pub fn detect(s: &str) -> Option<StringFormat> {
+        let m = matchers();
+        if m.date_time.is_match(s) {
+            Some(Self::DateTime)
+        } else if m.date.is_match(s) {
+            Some(Self::Date)
+        } else if m.uuid.is_match(s) {
+            Some(Self::Uuid)
+        } else if m.object_id.is_match(s) {
+            Some(Self::ObjectId)
+        } else if m.email.is_match(s) {
+            Some(Self::Email)
+        } else if m.uri.is_match(s) {
+            Some(Self::Uri)
+        } else if s.parse::<Ipv4Addr>().is_ok() {
+            Some(Self::Ipv4)
+        } else if s.parse::<Ipv6Addr>().is_ok() {
+            Some(Self::Ipv6)
+        } else if Self::is_base64(s) {
+            Some(Self::Base64)
+        } else {
+            None
+        }
+    }
+
+    /// Plain words are valid base64 too, so short strings and ones without padding
+    /// or a mix of cases and digits are left out.
+    fn is_base64(s: &str) -> bool {
+        let mixed = s.contains(|c: char| c.is_ascii_uppercase())
+            && s.contains(|c: char| c.is_ascii_lowercase())
+            && s.contains(|c: char| c.is_ascii_digit());
+        s.len() >= 16 && (s.ends_with('=') || mixed) && matchers().base64.is_match(s)
+    }
+
+    /// Name used by JSON Schema's `format`, ObjectId is not a standard one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DateTime => "date-time",
+            Self::Date => "date",
+            Self::Uuid => "uuid",
+            Self::Email => "email",
+            Self::Uri => "uri",
+            Self::Ipv4 => "ipv4",
+            Self::Ipv6 => "ipv6",
+            Self::ObjectId => "objectid",
+            Self::Base64 => "base64",
+        }
+    }
+}
+
+impl Property {
+    /// Missing from some of the objects.
+    pub fn is_optional(&self, object: &ObjectSchema) -> bool {
+        self.occurrences < object.samples
+    }
+}
+
+/// Deep merges objects, arrays are concatenated.
 pub fn merge(objects: Vec<Value>) -> Value {
     let first = objects.first();
     if first.is_some() && first.unwrap().is_array() {
@@ -15,11 +275,23 @@ fn merge_object(objects: Vec<Value>) -> Value {
     let mut result = Map::new();
     for obj in objects {
         if let Value::Object(map) = obj {
-            result.extend(map);
+            merge_into(&mut result, map);
         }
     }
     Value::Object(result)
 }
+
+fn merge_into(target: &mut Map<String, Value>, source: Map<String, Value>) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Object(existing)), Value::Object(value)) => merge_into(existing, value),
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
 fn merge_array(arr: Vec<Value>) -> Value {
     let mut result = vec![];
     for item in arr {
@@ -32,81 +304,209 @@ fn merge_array(arr: Vec<Value>) -> Value {
     Value::Array(result)
 }
 
-pub fn to_types(json: Value) -> String {
-    let root_name = if json.is_array() { "ArrayItem" } else { "Root" };
-    let mut type_map = HashMap::new();
-    type_of(root_name, &json, &mut type_map);
+/// Named definitions of generated types, identical definitions share one name and the other
+/// ones taking the same name get a numeric suffix.
+#[derive(Debug)]
+pub struct UniqueNames<T> {
+    pub defs: BTreeMap<String, T>,
+    // key of a definition to its name
+    names: HashMap<String, String>,
+    // names given out, with or without a definition
+    taken: HashSet<String>,
+}
 
-    let mut types = vec![];
-    let mut type_mapping = HashMap::new();
-    let mut root = String::new();
+impl<T> Default for UniqueNames<T> {
+    fn default() -> Self {
+        UniqueNames {
+            defs: BTreeMap::new(),
+            names: HashMap::new(),
+            taken: HashSet::new(),
+        }
+    }
+}
+
+impl<T> UniqueNames<T> {
+    /// `name`, with a numeric suffix if it is taken already.
+    pub fn reserve(&mut self, name: &str) -> String {
+        let mut unique = name.to_owned();
+        let mut suffix = 1;
+        while self.taken.contains(&unique) {
+            suffix += 1;
+            unique = format!("{name}{suffix}");
+        }
+        self.taken.insert(unique.clone());
+        unique
+    }
+
+    /// Name of the definition with an identical `key`, if there is one.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.names.get(key)
+    }
+
+    /// Places the definition under a name from `reserve`.
+    pub fn define(&mut self, name: &str, key: String, def: T) {
+        self.names.entry(key).or_insert_with(|| name.to_owned());
+        self.defs.insert(name.to_owned(), def);
+    }
 
-    for (ty, name) in type_map.into_iter() {
-        let t = vec![format!("type {name} = {{"), ty, "};".to_owned()].join("\n");
+    /// Name of the identical definition, or of the new one.
+    pub fn add(&mut self, name: &str, key: String, def: T) -> String {
+        if let Some(name) = self.names.get(&key) {
+            return name.clone();
+        }
+        let name = self.reserve(name);
+        self.define(&name, key, def);
+        name
+    }
+}
+
+/// Object types of the TypeScript output, identical bodies share one name. The first location
+/// found in property order names a type, `Root` is kept for the root type.
+struct TypeNames(UniqueNames<String>);
+
+impl Default for TypeNames {
+    fn default() -> Self {
+        let mut names = UniqueNames::default();
+        names.reserve("Root");
+        TypeNames(names)
+    }
+}
+
+/// TypeScript types of the schema. Nested object types are named after their path from the
+/// root, e.g. `Root.user.address` becomes `UserAddress`, and are listed in name order.
+pub fn to_types(schema: &Schema) -> String {
+    let mut names = TypeNames::default();
+    let root_alias = if schema.is_empty() {
+        // no samples, typed as the empty object the merged sample would be
+        object_type_of("", "Root", &ObjectSchema::default(), &mut names);
+        None
+    } else if schema.is_only_object() {
+        type_of("", "Root", schema, &mut names);
+        None
+    } else if let (true, Some(items)) = (schema.is_only_array(), &schema.array) {
+        let item = type_of("", "ArrayItem", items, &mut names);
+        Some(format!("type Root = Array<{item}>;"))
+    } else {
+        let ty = type_of("", "RootObject", schema, &mut names);
+        Some(format!("type Root = {ty};"))
+    };
+
+    let mut root = String::new();
+    let mut types = vec![];
+    for (name, body) in names.0.defs.into_iter() {
+        let t = [format!("type {name} = {{"), body, "};".to_owned()].join("\n");
         if name == *"Root" {
             root = t;
         } else {
-            type_mapping.insert(t.clone(), name);
             types.push(t);
         }
     }
 
-    types.sort_by(|a, b| {
-        type_mapping
-            .get(a)
-            .unwrap()
-            .cmp(type_mapping.get(b).unwrap())
-    });
-
     let schema = format!("{root}\n{}", types.join("\n"));
-    if json.is_array() {
-        format!("type Root = Array<ArrayItem>;{schema}")
-    } else {
-        schema
+    match root_alias {
+        Some(alias) => format!("{alias}{schema}"),
+        None => schema,
     }
 }
 
-fn type_of(name: &str, obj: &Value, addons: &mut HashMap<String, String>) -> Option<String> {
-    match obj {
-        Value::Null => None,
-        Value::Bool(_) => Some("boolean".to_owned()),
-        Value::Number(_) => Some("number".to_owned()),
-        Value::String(_) => Some("string".to_owned()),
-        Value::Array(arr) => {
-            let first = arr.first();
-            if let Some(val) = first {
-                let ty = type_of(name, val, addons);
-                if let Some(ty) = ty {
-                    return Some(format!("{ty}[]"));
-                }
-            }
-            Some("unknown[]".to_owned())
-        }
-        Value::Object(obj) => {
-            let mut name: Vec<char> = name.chars().collect();
-            name[0] = name[0].to_ascii_uppercase();
-            let name = name.into_iter().collect::<String>();
-            let mut types = vec![];
-
-            for (n, val) in obj {
-                let ty = type_of(n, val, addons);
-                if let Some(ty) = ty {
-                    types.push(format!("  {n}: {ty};"));
-                } else {
-                    types.push(format!("  {n}?: unknown;"));
-                }
-            }
-            let ty = types.join("\n");
-            let entry = addons.entry(ty).or_insert(name.clone());
-            if entry.len() > name.len() {
-                *entry = name;
-            }
+/// `path` is the name of the enclosing properties, `name` the one an object here would get.
+fn type_of(path: &str, name: &str, schema: &Schema, names: &mut TypeNames) -> String {
+    let mut types = vec![];
+    if let Some(values) = schema.enum_values() {
+        let literals = values.iter().map(|v| Value::String(v.clone()).to_string());
+        types.extend(literals);
+    } else if schema.string {
+        types.push("string".to_owned());
+    }
+    if schema.number {
+        types.push("number".to_owned());
+    }
+    if schema.boolean {
+        types.push("boolean".to_owned());
+    }
+    if let Some(obj) = &schema.object {
+        types.push(object_type_of(path, name, obj, names));
+    }
+    if let Some(items) = &schema.array {
+        let item = type_of(path, name, items, names);
+        if item.contains(' ') {
+            types.push(format!("({item})[]"));
+        } else {
+            types.push(format!("{item}[]"));
+        }
+    }
+    if types.is_empty() && !schema.null {
+        types.push("unknown".to_owned());
+    }
+    if schema.null {
+        types.push("null".to_owned());
+    }
+    types.join(" | ")
+}
 
-            Some(entry.clone())
+fn object_type_of(path: &str, name: &str, obj: &ObjectSchema, names: &mut TypeNames) -> String {
+    let types = obj
+        .properties
+        .iter()
+        .map(|(n, property)| {
+            if property.schema.is_only_null() {
+                // nothing to tell the type from
+                return format!("  {}?: unknown;", property_key(n));
+            }
+            let optional = if property.is_optional(obj) { "?" } else { "" };
+            let child = type_path(path, n);
+            let ty = type_of(&child, &child, &property.schema, names);
+            let format = match property.schema.format {
+                Some(format) if property.schema.string => format!(" // {}", format.name()),
+                _ => String::new(),
+            };
+            format!("  {}{optional}: {ty};{format}", property_key(n))
+        })
+        .collect::<Vec<_>>();
+    let body = types.join("\n");
+    match name {
+        "Root" if path.is_empty() => {
+            names.0.define(name, body.clone(), body);
+            name.to_owned()
         }
+        "" => names.0.add("Item", body.clone(), body),
+        _ => names.0.add(name, body.clone(), body),
+    }
+}
+
+/// Property names that are not identifiers, e.g. `@id` and `#text` of XML bodies, are quoted.
+fn property_key(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_owned()
+    } else {
+        Value::String(name.to_owned()).to_string()
     }
 }
 
+/// Name of the type of a property, e.g. `UserAddress` for `address` in `User`.
+pub fn type_path(path: &str, property: &str) -> String {
+    format!("{path}{}", pascal_case(property))
+}
+
+/// `user_address`, `user-address` and `userAddress` all become `UserAddress`.
+pub fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
 #[test]
 fn test_object_to_schema() {
     let json = r#"
@@ -132,7 +532,7 @@ type TestObj = {
   test: number;
   text: string;
 };"#;
-    let res = to_types(serde_json::from_str(json).unwrap());
+    let res = to_types(&Schema::infer(&serde_json::from_str(json).unwrap()));
     assert_eq!(res, ans);
 
     let json = r#"[
@@ -162,13 +562,13 @@ type ArrayItem = {
   dataHolderName: string;
   dataRequesterName: string;
   firstSignDate: number;
-  id: string;
+  id: string; // objectid
   ordinaryUserName?: unknown;
   originId?: unknown;
   secondSignDate: number;
   signState: number;
 };"#;
-    let res = to_types(serde_json::from_str(json).unwrap());
+    let res = to_types(&Schema::infer(&serde_json::from_str(json).unwrap()));
     assert_eq!(res, ans);
 }
 
@@ -181,7 +581,7 @@ fn test_obj_merging() {
     assert_eq!(
         merged,
         serde_json::json!(
-          {"name":"test","nestObj":{"id":"123","array":[1,2,3,4,5]},"id":"123"}
+          {"name":"test","nestObj":{"time":123,"id":"123","array":[1,2,3,4,5]},"id":"123"}
         )
     );
 
@@ -196,3 +596,155 @@ fn test_obj_merging() {
         )
     );
 }
+
+#[test]
+fn test_infer_from_all_samples() {
+    let samples = [
+        serde_json::json!([{ "a": 1, "nested": { "x": 1 } }, { "b": "x", "nested": { "y": null } }]),
+        serde_json::json!([{ "a": "1", "b": null, "nested": { "x": 2, "y": true } }]),
+    ];
+    let ans = r#"type Root = Array<ArrayItem>;
+type ArrayItem = {
+  a?: string | number;
+  b?: string | null;
+  nested: Nested;
+};
+type Nested = {
+  x?: number;
+  y?: boolean | null;
+};"#;
+    assert_eq!(to_types(&Schema::infer_all(samples.iter())), ans);
+
+    let mixed = serde_json::json!({ "list": [1, "a", null], "empty": [] });
+    let ans = r#"type Root = {
+  empty: unknown[];
+  list: (string | number | null)[];
+};
+"#;
+    assert_eq!(to_types(&Schema::infer(&mixed)), ans);
+
+    // endpoints without a body keep the empty root object
+    assert_eq!(to_types(&Schema::default()), "type Root = {\n\n};\n");
+    assert_eq!(
+        to_types(&Schema::default()),
+        to_types(&Schema::infer(&serde_json::json!({})))
+    );
+}
+
+#[test]
+fn test_type_naming() {
+    let samples = [
+        serde_json::json!({
+            "user": { "address": { "city": "a" }, "home": { "city": "b" } },
+            "userAddress": { "zip": 1 },
+            "root": { "id": 1 },
+            "testObj": { "id": 1 }
+        }),
+        serde_json::json!({
+            "root": { "id": 2 },
+            "user": { "address": { "city": "c" }, "home": { "city": "d" } },
+            "testObj": { "id": 2 },
+            "userAddress": { "zip": 2 }
+        }),
+    ];
+    let ans = r#"type Root = {
+  root: Root2;
+  testObj: Root2;
+  user: User;
+  userAddress: UserAddress2;
+};
+type Root2 = {
+  id: number;
+};
+type User = {
+  address: UserAddress;
+  home: UserAddress;
+};
+type UserAddress = {
+  city: string;
+};
+type UserAddress2 = {
+  zip: number;
+};"#;
+    let types = to_types(&Schema::infer_all(samples.iter()));
+    assert_eq!(types, ans);
+    // the order of samples and properties does not matter
+    let reversed = to_types(&Schema::infer_all(samples.iter().rev()));
+    assert_eq!(reversed, types);
+
+    let xml = serde_json::json!({ "@id": 1, "#text": "a", "$ref": null, "first-name": "b" });
+    assert_eq!(
+        to_types(&Schema::infer(&xml)),
+        r##"type Root = {
+  "#text": string;
+  $ref?: unknown;
+  "@id": number;
+  "first-name": string;
+};
+"##
+    );
+}
+
+#[test]
+fn test_string_formats() {
+    let cases = [
+        ("2024-02-29T12:30:00.123Z", Some(StringFormat::DateTime)),
+        ("2024-02-29 12:30:00+08:00", Some(StringFormat::DateTime)),
+        ("2024-02-29", Some(StringFormat::Date)),
+        ("2024-13-01", None),
+        (
+            "123e4567-e89b-12d3-a456-426614174000",
+            Some(StringFormat::Uuid),
+        ),
+        ("someone@example.com", Some(StringFormat::Email)),
+        ("https://example.com/a?b=c", Some(StringFormat::Uri)),
+        ("192.168.0.1", Some(StringFormat::Ipv4)),
+        ("::1", Some(StringFormat::Ipv6)),
+        ("61d58fabd7cb2766e01db3c6", Some(StringFormat::ObjectId)),
+        ("aGVsbG8gd29ybGQ=", Some(StringFormat::Base64)),
+        ("administrators12", None),
+        ("hello", None),
+    ];
+    for (s, format) in cases {
+        assert_eq!(StringFormat::detect(s), format, "{s}");
+    }
+
+    // the format is kept only if every string has it
+    let samples = (0..12)
+        .map(|i| {
+            let id = format!("123e4567-e89b-12d3-a456-4266141740{i:02}");
+            let role = if i % 3 == 0 { "admin" } else { "user" };
+            serde_json::json!({ "id": id, "role": role, "note": format!("n{i}") })
+        })
+        .collect::<Vec<_>>();
+    let mut schema = Schema::infer_all(samples.iter());
+    let ans = r#"type Root = {
+  id: string; // uuid
+  note: string;
+  role: "admin" | "user";
+};
+"#;
+    assert_eq!(to_types(&schema), ans);
+
+    schema.merge(&Schema::infer(&serde_json::json!({ "id": "x" })));
+    let id = &schema.object.as_ref().unwrap().properties["id"].schema;
+    assert_eq!(id.format, None);
+    assert_eq!(id.values, None);
+}
+
+#[test]
+fn test_unique_names() {
+    let mut names = UniqueNames::default();
+    assert_eq!(names.reserve("Root"), "Root");
+    assert_eq!(names.add("Root", "a".to_owned(), 1), "Root2");
+    assert_eq!(names.add("User", "b".to_owned(), 2), "User");
+    // identical definitions share the name
+    assert_eq!(names.add("Owner", "b".to_owned(), 2), "User");
+    assert_eq!(names.add("User", "c".to_owned(), 3), "User2");
+    names.define("Root", "d".to_owned(), 4);
+    assert_eq!(names.get("d"), Some(&"Root".to_owned()));
+    assert_eq!(
+        names.defs.keys().collect::<Vec<_>>(),
+        ["Root", "Root2", "User", "User2"]
+    );
+}
@@ -1,13 +1,19 @@
+mod cli;
+mod codegen;
 mod data;
 mod data_processor;
 mod env;
+mod graph_export;
 mod http_client;
+mod json_schema;
 mod json_utils;
+mod openapi;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Result,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use actix_web::{
@@ -17,13 +23,13 @@ use actix_web::{
     web::{Data, Json},
     App, HttpResponse, HttpServer, Responder,
 };
-use data::connection_package::RequestPackage;
+use data::connection_package::{CodegenRequest, ExportRequest, OpenApiRequest, RequestPackage};
 use env::Env;
 use http_client::{kubernetes::KubernetesClient, url_matcher::UrlMatcher, zipkin::ZipkinClient};
 use log::{debug, error};
 use tokio::join;
 
-use crate::data_processor::{collect_data, DataProcessorState};
+use crate::data_processor::{process_request, DataProcessorState, ResponseCache};
 
 #[get("/")]
 async fn health() -> impl Responder {
@@ -35,7 +41,7 @@ async fn process_data(
     request: Json<RequestPackage>,
     state: Data<DataProcessorState>,
 ) -> impl Responder {
-    let resp = collect_data(request.0, state).await;
+    let resp = process_request(request.0, state).await;
     match resp {
         Ok(resp) => HttpResponse::Ok().json(resp),
         Err(err) => {
@@ -45,6 +51,43 @@ async fn process_data(
     }
 }
 
+#[post("/export")]
+async fn export_graph(request: Json<ExportRequest>) -> impl Responder {
+    let request = request.0;
+    HttpResponse::Ok()
+        .content_type(request.options.format.content_type())
+        .body(graph_export::export(
+            &request.dependencies,
+            &request.options,
+        ))
+}
+
+#[post("/openapi")]
+async fn openapi_documents(
+    request: Json<OpenApiRequest>,
+    state: Data<DataProcessorState>,
+) -> impl Responder {
+    HttpResponse::Ok().json(openapi::generate(
+        &request.datatypes,
+        &request.endpoints,
+        &state.url_matcher,
+    ))
+}
+
+#[post("/codegen")]
+async fn generate_models(
+    request: Json<CodegenRequest>,
+    state: Data<DataProcessorState>,
+) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(codegen::from_datatypes(
+            &request.datatypes,
+            request.language,
+            &state.url_matcher,
+        ))
+}
+
 async fn on_load(env: Arc<Env>) -> Result<()> {
     debug!("Dumping environment:\n{:#?}", env);
     Ok(())
@@ -52,12 +95,27 @@ async fn on_load(env: Arc<Env>) -> Result<()> {
 
 #[actix_web::main]
 async fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        if let Err(err) = cli::run(&args) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let env = Arc::new(env::Env::new());
     env_logger::init();
     let kubernetes = Arc::new(KubernetesClient::new(env.clone()));
     let zipkin = Arc::new(ZipkinClient::new(env.clone()));
     let url_matcher = Arc::new(UrlMatcher::new());
     let processed = Arc::new(Mutex::new(HashMap::new()));
+    let deferred = Arc::new(Mutex::new(HashSet::new()));
+    let sampling_rate = env.sampling_rate;
+    let queue = Arc::new(tokio::sync::Mutex::new(()));
+    let responses = Arc::new(Mutex::new(ResponseCache::new(Duration::from_secs(
+        env.response_ttl,
+    ))));
 
     let server = HttpServer::new(move || {
         App::new()
@@ -66,10 +124,17 @@ async fn main() -> Result<()> {
                 zipkin: zipkin.clone(),
                 url_matcher: url_matcher.clone(),
                 processed: processed.clone(),
+                deferred: deferred.clone(),
+                sampling_rate,
+                queue: queue.clone(),
+                responses: responses.clone(),
             }))
             .wrap(Compress::default())
             .service(health)
             .service(process_data)
+            .service(export_graph)
+            .service(openapi_documents)
+            .service(generate_models)
     })
     .bind((env.bind_ip.as_str(), env.port))?
     .run();
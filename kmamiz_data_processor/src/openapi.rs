@@ -0,0 +1,720 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    data::{
+        body_sampler,
+        endpoint_data_type::{EndpointDataSchema, EndpointDataType},
+        endpoint_info::EndpointInfo,
+        request_type::RequestType,
+    },
+    http_client::url_matcher::UrlMatcher,
+    json_schema,
+    json_utils::{Schema, UniqueNames},
+};
+
+static OPENAPI_VERSION: &str = "3.1.0";
+// recorded URLs listed in an operation's description
+const MAX_EXAMPLES: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceDocument {
+    pub unique_service_name: String,
+    pub service: String,
+    pub namespace: String,
+    pub version: String,
+    pub document: Value,
+}
+
+/// Component schemas of a document, identical schemas share one name.
+#[derive(Debug, Default)]
+struct Components {
+    schemas: UniqueNames<Value>,
+}
+
+/// OpenAPI 3.1 documents, one per service. `endpoints` adds recorded URLs to the operations.
+pub fn generate(
+    datatypes: &[EndpointDataType],
+    endpoints: &[EndpointInfo],
+    url_matcher: &UrlMatcher,
+) -> Vec<ServiceDocument> {
+    let mut services: BTreeMap<&str, Vec<&EndpointDataType>> = BTreeMap::new();
+    for datatype in datatypes.iter() {
+        services
+            .entry(&datatype.unique_service_name)
+            .or_default()
+            .push(datatype);
+    }
+
+    services
+        .into_values()
+        .map(|datatypes| {
+            let first = datatypes[0];
+            ServiceDocument {
+                unique_service_name: first.unique_service_name.clone(),
+                service: first.service.clone(),
+                namespace: first.namespace.clone(),
+                version: first.version.clone(),
+                document: service_document(&datatypes, endpoints, url_matcher),
+            }
+        })
+        .collect()
+}
+
+fn service_document(
+    datatypes: &[&EndpointDataType],
+    endpoints: &[EndpointInfo],
+    url_matcher: &UrlMatcher,
+) -> Value {
+    // (path template, method) to the datatypes recorded for it
+    let mut operations: BTreeMap<(String, &str), Vec<&EndpointDataType>> = BTreeMap::new();
+    for datatype in datatypes.iter() {
+        let Some(method) = method_of(&datatype.method) else {
+            continue;
+        };
+        let path = path_of(datatype, url_matcher);
+        operations.entry((path, method)).or_default().push(datatype);
+    }
+
+    let mut components = Components::default();
+    let mut operation_ids = BTreeSet::new();
+    let mut paths: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+    for ((path, method), datatypes) in operations.into_iter() {
+        let (template, path_params) = to_template(&path);
+        let operation = operation_of(
+            &template,
+            method,
+            &path_params,
+            &datatypes,
+            endpoints,
+            &mut components,
+            &mut operation_ids,
+        );
+        paths
+            .entry(template)
+            .or_default()
+            .insert(method.to_owned(), operation);
+    }
+
+    let first = datatypes[0];
+    let mut document = json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": format!("{}.{}", first.service, first.namespace),
+            "version": first.version,
+        },
+        "paths": paths,
+    });
+    if !components.schemas.defs.is_empty() {
+        document["components"] = json!({ "schemas": components.schemas.defs });
+    }
+    document
+}
+
+fn operation_of(
+    template: &str,
+    method: &str,
+    path_params: &[String],
+    datatypes: &[&EndpointDataType],
+    endpoints: &[EndpointInfo],
+    components: &mut Components,
+    operation_ids: &mut BTreeSet<String>,
+) -> Value {
+    let schemas = datatypes
+        .iter()
+        .flat_map(|d| d.schemas.iter())
+        .collect::<Vec<_>>();
+
+    let mut parameters = path_params
+        .iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut query = BTreeMap::new();
+    for datatype in datatypes.iter() {
+        for (name, ty) in query_params_of(url_of(datatype)) {
+            query.entry(name).or_insert(ty);
+        }
+    }
+    for params in schemas.iter().filter_map(|s| s.request_params.as_ref()) {
+        for param in params.iter() {
+            query.insert(param.param.clone(), schema_type_of(&param.r#type));
+        }
+    }
+    // a parameter is identified by name and location, the path one is kept
+    query.retain(|name, _| !path_params.contains(name));
+    parameters.extend(query.into_iter().map(|(name, ty)| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "schema": { "type": ty },
+        })
+    }));
+
+    let mut operation = json!({
+        "operationId": operation_id(method, template, operation_ids),
+        "description": description_of(datatypes, endpoints),
+    });
+    if !parameters.is_empty() {
+        operation["parameters"] = json!(parameters);
+    }
+
+    let request = content_of(schemas.iter().map(|s| Body::request(s)), components);
+    if !request.is_empty() {
+        operation["requestBody"] = json!({ "content": request });
+    }
+
+    let mut statuses: BTreeMap<String, Vec<&EndpointDataSchema>> = BTreeMap::new();
+    for schema in schemas.iter() {
+        statuses
+            .entry(status_of(&schema.status))
+            .or_default()
+            .push(schema);
+    }
+    let mut responses = Map::new();
+    for (status, schemas) in statuses.into_iter() {
+        let content = content_of(schemas.iter().map(|s| Body::response(s)), components);
+        let mut response = json!({ "description": description_of_status(&status) });
+        if !content.is_empty() {
+            response["content"] = json!(content);
+        }
+        responses.insert(status, response);
+    }
+    if responses.is_empty() {
+        responses.insert(
+            "default".to_owned(),
+            json!({ "description": "No recorded response" }),
+        );
+    }
+    operation["responses"] = Value::Object(responses);
+    operation
+}
+
+/// Request or response side of a recorded schema.
+struct Body<'a> {
+    time: i64,
+    content_type: Option<&'a str>,
+    sample: Option<&'a str>,
+    json_schema: Option<&'a Value>,
+}
+
+impl<'a> Body<'a> {
+    fn request(schema: &'a EndpointDataSchema) -> Self {
+        Body {
+            time: schema.time,
+            content_type: schema.request_content_type.as_deref(),
+            sample: schema.request_sample.as_deref(),
+            json_schema: schema.request_json_schema.as_ref(),
+        }
+    }
+
+    fn response(schema: &'a EndpointDataSchema) -> Self {
+        Body {
+            time: schema.time,
+            content_type: schema.response_content_type.as_deref(),
+            sample: schema.response_sample.as_deref(),
+            json_schema: schema.response_json_schema.as_ref(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MediaType<'a> {
+    samples: Vec<Value>,
+    // the latest one
+    example: Option<(i64, Value)>,
+    // distinct stored schemas
+    json_schemas: Vec<&'a Value>,
+}
+
+/// Media types of a request or response body. The stored JSON Schemas are used, inferred from
+/// every body, and only without them the schema is inferred from the merged samples. The latest
+/// sample becomes the example, placeholders of truncated bodies are left out.
+fn content_of<'a>(
+    bodies: impl Iterator<Item = Body<'a>>,
+    components: &mut Components,
+) -> Map<String, Value> {
+    let mut media: BTreeMap<String, MediaType> = BTreeMap::new();
+    for body in bodies {
+        let sample = body
+            .sample
+            .and_then(|s| serde_json::from_str::<Value>(s).ok())
+            .filter(|s| !is_empty_body(s) && !body_sampler::is_truncated(s));
+        if sample.is_none() && body.json_schema.is_none() && body.content_type.is_none() {
+            continue;
+        }
+        let entry = media.entry(media_type_of(body.content_type)).or_default();
+        if let Some(sample) = sample {
+            if entry.example.as_ref().is_none_or(|(t, _)| *t <= body.time) {
+                entry.example = Some((body.time, sample.clone()));
+            }
+            entry.samples.push(sample);
+        }
+        if let Some(json_schema) = body.json_schema {
+            if !entry.json_schemas.contains(&json_schema) {
+                entry.json_schemas.push(json_schema);
+            }
+        }
+    }
+
+    let mut content = Map::new();
+    for (media_type, entry) in media.into_iter() {
+        let mut schemas = vec![];
+        for json_schema in entry.json_schemas.into_iter() {
+            let lifted = components.lift(json_schema.clone());
+            if !schemas.contains(&lifted) {
+                schemas.push(lifted);
+            }
+        }
+        if schemas.is_empty() && !entry.samples.is_empty() {
+            let inferred = json_schema::to_json_schema(&Schema::infer_all(entry.samples.iter()));
+            schemas.push(components.lift(inferred));
+        }
+        let schema = match schemas.len() {
+            0 => None,
+            1 => schemas.pop(),
+            _ => Some(json!({ "anyOf": schemas })),
+        };
+        let mut object = Map::new();
+        if let Some(schema) = schema {
+            object.insert("schema".to_owned(), schema);
+        }
+        if let Some((_, example)) = entry.example {
+            object.insert("example".to_owned(), example);
+        }
+        content.insert(media_type, Value::Object(object));
+    }
+    content
+}
+
+impl Components {
+    /// Moves `$defs` of a standalone JSON Schema into the components, rewriting the references.
+    fn lift(&mut self, mut schema: Value) -> Value {
+        let Value::Object(root) = &mut schema else {
+            return schema;
+        };
+        root.remove("$schema");
+        let mut defs = match root.remove("$defs") {
+            Some(Value::Object(defs)) => defs,
+            _ => Map::new(),
+        };
+
+        // definitions are added after the ones they reference, so that identical ones are found
+        let mut mapping = HashMap::new();
+        while !defs.is_empty() {
+            let ready = defs
+                .iter()
+                .find(|(_, def)| refs_of(def).iter().all(|r| mapping.contains_key(r)))
+                .or_else(|| defs.iter().next())
+                .map(|(name, _)| name.clone())
+                .unwrap();
+            let mut def = defs.remove(&ready).unwrap();
+            rewrite_refs(&mut def, &mapping);
+            let name = self.add(&ready, def);
+            mapping.insert(ready, name);
+        }
+        rewrite_refs(&mut schema, &mapping);
+        schema
+    }
+
+    fn add(&mut self, name: &str, schema: Value) -> String {
+        // component names are limited to ^[a-zA-Z0-9._-]+$
+        let name = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        self.schemas.add(&name, schema.to_string(), schema)
+    }
+}
+
+fn refs_of(schema: &Value) -> Vec<String> {
+    match schema {
+        Value::Object(map) => map
+            .iter()
+            .flat_map(|(key, value)| match (key.as_str(), value) {
+                ("$ref", Value::String(r)) => r
+                    .strip_prefix("#/$defs/")
+                    .map(|r| vec![r.to_owned()])
+                    .unwrap_or_default(),
+                _ => refs_of(value),
+            })
+            .collect(),
+        Value::Array(arr) => arr.iter().flat_map(refs_of).collect(),
+        _ => vec![],
+    }
+}
+
+fn rewrite_refs(schema: &mut Value, mapping: &HashMap<String, String>) {
+    match schema {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if let ("$ref", Value::String(r)) = (key.as_str(), &value) {
+                    if let Some(name) = r.strip_prefix("#/$defs/").and_then(|r| mapping.get(r)) {
+                        *value = json!(format!("#/components/schemas/{name}"));
+                    }
+                } else {
+                    rewrite_refs(value, mapping);
+                }
+            }
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(|v| rewrite_refs(v, mapping)),
+        _ => {}
+    }
+}
+
+/// Bodies typed from no samples are merged into an empty object.
+pub fn is_empty_body(sample: &Value) -> bool {
+    match sample {
+        Value::Object(map) => map.is_empty(),
+        Value::Null => true,
+        _ => false,
+    }
+}
+
+fn media_type_of(content_type: Option<&str>) -> String {
+    content_type
+        .and_then(|c| c.split(';').next())
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty())
+        .unwrap_or_else(|| "application/json".to_owned())
+}
+
+fn method_of(method: &RequestType) -> Option<&'static str> {
+    match method {
+        RequestType::Get => Some("get"),
+        RequestType::Post => Some("post"),
+        RequestType::Put => Some("put"),
+        RequestType::Patch => Some("patch"),
+        RequestType::Delete => Some("delete"),
+        RequestType::Head => Some("head"),
+        RequestType::Options => Some("options"),
+        RequestType::Trace => Some("trace"),
+        // not an operation in OpenAPI
+        RequestType::Connect => None,
+    }
+}
+
+fn url_of(datatype: &EndpointDataType) -> &str {
+    datatype
+        .unique_endpoint_name
+        .rsplit('\t')
+        .next()
+        .unwrap_or_default()
+}
+
+/// The label if there is one, the recorded URL's path otherwise.
+pub fn path_of(datatype: &EndpointDataType, url_matcher: &UrlMatcher) -> String {
+    let path = match datatype.label_name.as_deref() {
+        Some(label) if label.starts_with('/') => label.to_owned(),
+        _ => url_matcher
+            .explode_url(url_of(datatype), false)
+            .path
+            .unwrap_or_default(),
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Names the anonymous `{}` segments of a label, returns the template and its parameters.
+pub fn to_template(path: &str) -> (String, Vec<String>) {
+    let mut params = vec![];
+    let segments = path
+        .split('/')
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => {
+                    let name = if name.is_empty() {
+                        format!("param{}", params.len() + 1)
+                    } else {
+                        name.to_owned()
+                    };
+                    let segment = format!("{{{name}}}");
+                    if !params.contains(&name) {
+                        params.push(name);
+                    }
+                    segment
+                }
+                None => segment.to_owned(),
+            },
+        )
+        .collect::<Vec<_>>();
+    (segments.join("/"), params)
+}
+
+fn query_params_of(url: &str) -> Vec<(String, &'static str)> {
+    let Some((_, query)) = url.split_once('?') else {
+        return vec![];
+    };
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if name.is_empty() {
+                return None;
+            }
+            let ty = if value.parse::<f64>().is_ok() {
+                "number"
+            } else if value == "true" || value == "false" {
+                "boolean"
+            } else {
+                "string"
+            };
+            Some((name.to_owned(), ty))
+        })
+        .collect()
+}
+
+fn schema_type_of(ty: &str) -> &'static str {
+    match ty {
+        "number" | "bigint" => "number",
+        "integer" => "integer",
+        "boolean" => "boolean",
+        "object" => "object",
+        "array" => "array",
+        _ => "string",
+    }
+}
+
+fn status_of(status: &str) -> String {
+    let is_code = status.len() == 3 && status.chars().all(|c| c.is_ascii_digit());
+    if is_code {
+        status.to_owned()
+    } else {
+        "default".to_owned()
+    }
+}
+
+fn description_of_status(status: &str) -> String {
+    match status {
+        "default" => "Unrecognized status".to_owned(),
+        status => format!("Status {status}"),
+    }
+}
+
+fn operation_id(method: &str, template: &str, operation_ids: &mut BTreeSet<String>) -> String {
+    let mut id = method.to_owned();
+    for word in template.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            id.push(first.to_ascii_uppercase());
+            id.extend(chars);
+        }
+    }
+    let mut unique = id.clone();
+    let mut suffix = 1;
+    while operation_ids.contains(&unique) {
+        suffix += 1;
+        unique = format!("{id}{suffix}");
+    }
+    operation_ids.insert(unique.clone());
+    unique
+}
+
+fn description_of(datatypes: &[&EndpointDataType], endpoints: &[EndpointInfo]) -> String {
+    let mut urls = BTreeSet::new();
+    for datatype in datatypes.iter() {
+        urls.insert(url_of(datatype).to_owned());
+        let recorded = endpoints.iter().filter(|e| {
+            e.unique_endpoint_name == datatype.unique_endpoint_name
+                || (datatype.label_name.is_some()
+                    && e.unique_service_name == datatype.unique_service_name
+                    && e.method == datatype.method
+                    && e.label_name == datatype.label_name)
+        });
+        urls.extend(recorded.map(|e| e.url.clone()));
+    }
+    let examples = urls
+        .into_iter()
+        .take(MAX_EXAMPLES)
+        .map(|url| format!("  - {url}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("**Recorded examples:**\n\n{examples}")
+}
+
+#[test]
+fn test_generate() {
+    use crate::data::endpoint_data_type::EndpointRequestParams;
+
+    let schema = |status: &str, request: Option<&str>, response: Option<&str>| EndpointDataSchema {
+        time: 0,
+        status: status.to_owned(),
+        request_sample: request.map(|s| s.to_owned()),
+        request_schema: None,
+        request_json_schema: None,
+        request_content_type: request.map(|_| "application/json; charset=utf-8".to_owned()),
+        request_params: None,
+        response_sample: response.map(|s| s.to_owned()),
+        response_schema: None,
+        response_json_schema: None,
+        request_statistics: None,
+        response_statistics: None,
+        response_content_type: response.map(|_| "application/json".to_owned()),
+    };
+    let datatype = |method: RequestType, url: &str, label: Option<&str>, schemas| {
+        let unique_service_name = "users\tns\tv1".to_owned();
+        EndpointDataType {
+            _id: None,
+            unique_endpoint_name: format!("{unique_service_name}\t{method:?}\t{url}"),
+            unique_service_name,
+            service: "users".to_owned(),
+            namespace: "ns".to_owned(),
+            version: "v1".to_owned(),
+            label_name: label.map(|l| l.to_owned()),
+            method,
+            schemas,
+        }
+    };
+    let mut list = datatype(
+        RequestType::Get,
+        "http://users.ns.svc.cluster.local/users?limit=10",
+        None,
+        vec![schema(
+            "200",
+            None,
+            Some(r#"[{"id":1,"address":{"city":"a"}}]"#),
+        )],
+    );
+    list.schemas[0].request_params = Some(vec![EndpointRequestParams {
+        param: "active".to_owned(),
+        r#type: "boolean".to_owned(),
+    }]);
+    // the merged sample has every field, the stored schema knows which ones are optional
+    let stored =
+        Schema::infer_all([json!({ "id": 1, "nickname": "a" }), json!({ "id": 2 })].iter());
+    let mut found = schema("200", None, Some(r#"{"id":1,"nickname":"a"}"#));
+    found.response_json_schema = Some(json_schema::to_json_schema(&stored));
+    let user = datatype(
+        RequestType::Get,
+        "http://users.ns.svc.cluster.local/users/1",
+        Some("/users/{id}"),
+        vec![
+            found,
+            schema("500", None, Some(r#""<truncated, 70000 bytes>""#)),
+        ],
+    );
+    let datatypes = vec![
+        list,
+        datatype(
+            RequestType::Post,
+            "http://users.ns.svc.cluster.local/users/1/address",
+            Some("/users/{}/address"),
+            vec![
+                schema("201", Some(r#"{"city":"b"}"#), Some(r#"{"city":"b"}"#)),
+                schema("400", Some(r#"{"city":1}"#), None),
+            ],
+        ),
+        datatype(
+            RequestType::Connect,
+            "http://users.ns.svc.cluster.local/",
+            None,
+            vec![],
+        ),
+        datatype(
+            RequestType::Delete,
+            "http://users.ns.svc.cluster.local/users/1?id=1&force=true",
+            Some("/users/{id}"),
+            vec![schema("204", None, None)],
+        ),
+        user,
+    ];
+
+    let documents = generate(&datatypes, &[], &UrlMatcher::new());
+    assert_eq!(documents.len(), 1);
+    let document = &documents[0].document;
+    assert_eq!(document["openapi"], "3.1.0");
+    assert_eq!(document["info"]["title"], "users.ns");
+    assert_eq!(document["paths"].as_object().unwrap().len(), 3);
+
+    let openapi_schema =
+        serde_json::from_str(include_str!("../resources/openapi-3.1.schema.json")).unwrap();
+    let validator = jsonschema::validator_for(&openapi_schema).unwrap();
+    let errors = validator
+        .iter_errors(document)
+        .map(|e| format!("{} at {}", e, e.instance_path))
+        .collect::<Vec<_>>();
+    assert!(errors.is_empty(), "{errors:#?}");
+
+    let get = &document["paths"]["/users"]["get"];
+    assert_eq!(get["operationId"], "getUsers");
+    let params = get["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            (
+                p["name"].as_str().unwrap(),
+                p["schema"]["type"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(params, vec![("active", "boolean"), ("limit", "number")]);
+    let ok = &get["responses"]["200"]["content"]["application/json"];
+    assert_eq!(
+        ok["schema"]["items"]["$ref"],
+        "#/components/schemas/ArrayItem"
+    );
+    assert_eq!(ok["example"][0]["id"], 1);
+
+    let post = &document["paths"]["/users/{param1}/address"]["post"];
+    assert_eq!(post["parameters"][0]["in"], "path");
+    assert_eq!(post["parameters"][0]["required"], true);
+    let body = &post["requestBody"]["content"]["application/json"]["schema"];
+    assert_eq!(
+        body["properties"]["city"]["type"],
+        json!(["string", "number"])
+    );
+    assert!(post["responses"]["400"].get("content").is_none());
+    assert_eq!(
+        post["responses"]["201"]["content"]["application/json"]["schema"]["properties"]["city"]
+            ["type"],
+        "string"
+    );
+
+    let delete = &document["paths"]["/users/{id}"]["delete"];
+    let params = delete["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["name"].as_str().unwrap(), p["in"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(params, vec![("id", "path"), ("force", "query")]);
+
+    let get = &document["paths"]["/users/{id}"]["get"]["responses"];
+    let found = &get["200"]["content"]["application/json"];
+    assert_eq!(found["schema"]["required"], json!(["id"]));
+    assert_eq!(found["example"]["nickname"], "a");
+    // nothing to tell from a truncated body
+    assert_eq!(get["500"]["content"]["application/json"], json!({}));
+
+    // the address shares its definition with the one in the list
+    let schemas = document["components"]["schemas"].as_object().unwrap();
+    assert_eq!(
+        schemas.keys().collect::<Vec<_>>(),
+        vec!["Address", "ArrayItem"]
+    );
+    assert_eq!(
+        schemas["ArrayItem"]["properties"]["address"]["$ref"],
+        "#/components/schemas/Address"
+    );
+}